@@ -49,6 +49,35 @@ pub(crate) fn get_app<'a, 'b>() -> App<'a, 'b> {
                 .default_value("out")
                 .value_name("DIRECTORY"),
         )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .help("The profile from the config file to use")
+                .takes_value(true)
+                .global(true)
+                .value_name("PROFILE"),
+        )
+        .arg(
+            Arg::with_name("define")
+                .short("D")
+                .help("Set a global variable or setting, overriding the config and vars.toml")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true)
+                .value_name("KEY=VALUE"),
+        )
+        .arg(
+            Arg::with_name("features")
+                .long("features")
+                .help("Features to enable for conditional compilation")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .number_of_values(1)
+                .global(true)
+                .value_name("FEATURES"),
+        )
         .arg(
             Arg::with_name("ignore-config")
                 .long("ignore-config")
@@ -88,6 +117,16 @@ pub(crate) fn get_app<'a, 'b>() -> App<'a, 'b> {
                         .value_name("VERSION"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("expand")
+                .about("Print a file with its macros expanded")
+                .arg(
+                    Arg::with_name("file")
+                        .help("The file to expand")
+                        .required(true)
+                        .value_name("FILE"),
+                ),
+        )
 }
 
 /// Get matches for the CLI. If no arguments are passed, checks if the current
@@ -15,33 +15,10 @@
  * You should have received a copy of the GNU General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
-use databind::Settings;
-use serde::Serialize;
+use databind::{pack::Pack, Settings};
 use std::fs;
 use std::path::{Path, PathBuf};
 
-fn make_pack_mcmeta(description: String, version: u8) -> Result<String, serde_json::Error> {
-    #[derive(Serialize)]
-    struct Pack {
-        pack_format: u8,
-        description: String,
-    }
-
-    #[derive(Serialize)]
-    struct PackMcMeta {
-        pack: Pack,
-    }
-
-    let pack = PackMcMeta {
-        pack: Pack {
-            pack_format: version,
-            description,
-        },
-    };
-
-    serde_json::to_string(&pack)
-}
-
 fn dir_empty(path: &dyn AsRef<Path>) -> std::io::Result<bool> {
     Ok(path.as_ref().read_dir()?.next().is_none())
 }
@@ -67,7 +44,13 @@ pub(crate) fn create_project(args: clap::ArgMatches) -> std::io::Result<()> {
     } else {
         name
     };
-    let version: u8 = args.value_of("version").unwrap().parse().unwrap();
+    let version: u32 = match args.value_of("version").unwrap().parse() {
+        Ok(version) => version,
+        Err(_) => {
+            eprintln!("Pack format must be a positive integer");
+            std::process::exit(1);
+        }
+    };
 
     let mut path = PathBuf::from(base_path);
 
@@ -100,25 +83,26 @@ pub(crate) fn create_project(args: clap::ArgMatches) -> std::io::Result<()> {
     path.pop();
     path.pop();
     path.pop();
-    path.pop();
-
-    // Create pack.mcmeta
-    let pack_mcmeta = make_pack_mcmeta(description, version)?;
-    path.push("pack.mcmeta");
-    fs::write(&path, pack_mcmeta)?;
-
     path.pop();
     path.pop();
 
-    // Create databind.toml
-    let databind_toml = toml::to_string(&Settings::default()).unwrap();
+    // Create databind.toml, with the pack metadata used for pack.mcmeta
+    let settings = Settings {
+        pack: Some(Pack {
+            pack_format: Some(version),
+            description: description.into(),
+            ..Pack::default()
+        }),
+        ..Settings::default()
+    };
+    let databind_toml = toml::to_string(&settings).unwrap();
     path.push("databind.toml");
     fs::write(&path, databind_toml)?;
 
     println!(
         "Created project {} in {}",
         name,
-        fs::canonicalize(&base_path).unwrap().display()
+        fs::canonicalize(base_path).unwrap().display()
     );
     Ok(())
 }
@@ -0,0 +1,306 @@
+/*
+ * Databind - Expand the functionality of Minecraft Datapacks.
+ * Copyright (C) 2021  Adam Thompson-Sharpe
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Settings for directories of a project that have their own `databind.toml`
+use clap::ArgMatches;
+use databind::{files, Settings};
+use same_file::is_same_file;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+/// Where the functions in a directory are put when its
+/// `databind.toml` sets `namespace` or `subfolder`
+#[derive(Clone)]
+struct Location {
+    /// The directory that the namespace or subfolder was set for
+    dir: PathBuf,
+    /// The folder that namespaces are in, relative to the source folder (eg. `data`)
+    data_dir: String,
+    namespace: String,
+    /// The subfolder prefix for functions directly in `dir` (eg. `utils/`)
+    prefix: String,
+}
+
+/// Settings for the files in a directory, with the `databind.toml`
+/// files of the directory and its parents applied
+pub(crate) struct DirSettings {
+    pub settings: Settings,
+    /// Global variables with the directory's `vars` setting applied
+    pub vars: HashMap<String, String>,
+    inclusions: Vec<PathBuf>,
+    exclusions: Vec<PathBuf>,
+    location: Option<Location>,
+}
+
+impl DirSettings {
+    fn new(
+        settings: Settings,
+        datapack: &str,
+        builtin_vars: &HashMap<String, String>,
+        matches: &ArgMatches,
+        location: Option<Location>,
+    ) -> Self {
+        let vars = crate::load_vars(builtin_vars, datapack, &settings, matches);
+        let exclusions = files::merge_globs(&settings.exclusions, datapack);
+        let mut inclusions = files::merge_globs(&settings.inclusions, datapack);
+        inclusions.retain(|x| !exclusions.contains(x));
+
+        DirSettings {
+            settings,
+            vars,
+            inclusions,
+            exclusions,
+            location,
+        }
+    }
+
+    /// Check whether a file should be compiled
+    pub fn is_included(&self, path: &Path) -> bool {
+        matches_any(&self.inclusions, path) && !self.is_excluded(path)
+    }
+
+    /// Check whether a file should be left out of the output
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        matches_any(&self.exclusions, path)
+    }
+
+    /// Get the namespace of a file
+    pub fn namespace(&self, path: &Path) -> Option<String> {
+        match &self.location {
+            Some(location) => Some(location.namespace.clone()),
+            None => files::get_namespace(&path).ok().map(|x| x.into()),
+        }
+    }
+
+    /// Get the subfolder prefix used for calls in a file (eg. `cmd/`)
+    pub fn subfolder(&self, path: &Path) -> String {
+        let location = match &self.location {
+            Some(location) => location,
+            None => return files::get_subfolder_prefix(&path),
+        };
+
+        let parent = path.canonicalize().unwrap();
+        let parent = parent.parent().unwrap();
+        let relative = parent.strip_prefix(&location.dir).unwrap();
+        let relative: Vec<_> = relative
+            .components()
+            .map(|x| x.as_os_str().to_str().unwrap())
+            .collect();
+
+        if relative.is_empty() {
+            location.prefix.clone()
+        } else {
+            format!("{}{}/", location.prefix, relative.join("/"))
+        }
+    }
+
+    /// Get the output folder for a file. `relative_path` is the file's
+    /// path relative to the source folder
+    pub fn target_dir(&self, path: &Path, relative_path: &Path, output: &str) -> String {
+        match &self.location {
+            Some(location) => format!(
+                "{}/{}/{}/functions/{}",
+                output,
+                location.data_dir,
+                location.namespace,
+                self.subfolder(path),
+            ),
+            None => format!(
+                "{}/{}",
+                output,
+                relative_path.parent().unwrap().to_str().unwrap()
+            ),
+        }
+    }
+}
+
+/// The settings of each directory in a project
+pub(crate) struct DirConfigs<'a> {
+    datapack: &'a str,
+    /// The canonical path of the project
+    root_dir: PathBuf,
+    /// The canonical path of the source folder
+    src_dir: PathBuf,
+    /// The built-in global variables, shared by every directory
+    builtin_vars: HashMap<String, String>,
+    matches: &'a ArgMatches<'a>,
+    root: Rc<DirSettings>,
+    cache: HashMap<PathBuf, Rc<DirSettings>>,
+}
+
+impl<'a> DirConfigs<'a> {
+    /// Create settings for the directories of a project,
+    /// with `settings` being the ones from the project's config
+    pub fn new(
+        datapack: &'a str,
+        src_dir: &Path,
+        settings: Settings,
+        builtin_vars: HashMap<String, String>,
+        matches: &'a ArgMatches<'a>,
+    ) -> std::io::Result<Self> {
+        for (setting, value) in [
+            ("namespace", &settings.namespace),
+            ("subfolder", &settings.subfolder),
+        ] {
+            if value.is_some() {
+                eprintln!(
+                    "error: The {} setting can only be used in the databind.toml of a subdirectory",
+                    setting
+                );
+                std::process::exit(1);
+            }
+        }
+
+        let root = DirSettings::new(settings, datapack, &builtin_vars, matches, None);
+
+        Ok(DirConfigs {
+            datapack,
+            root_dir: Path::new(datapack).canonicalize()?,
+            src_dir: src_dir.canonicalize()?,
+            builtin_vars,
+            matches,
+            root: Rc::new(root),
+            cache: HashMap::new(),
+        })
+    }
+
+    /// Get the settings for a file from the `databind.toml` files
+    /// in its directory and the ones above it
+    pub fn for_file(&mut self, path: &Path) -> std::io::Result<Rc<DirSettings>> {
+        let dir = path.canonicalize()?;
+        self.get(dir.parent().unwrap())
+    }
+
+    /// Get the settings for a directory. `dir` is canonical
+    fn get(&mut self, dir: &Path) -> std::io::Result<Rc<DirSettings>> {
+        // Only the source folder and the folders in it can have their own settings
+        if dir == self.root_dir || !dir.starts_with(&self.src_dir) {
+            return Ok(self.root.clone());
+        }
+
+        if let Some(settings) = self.cache.get(dir) {
+            return Ok(settings.clone());
+        }
+
+        let parent = self.get(dir.parent().unwrap())?;
+        let config_path = dir.join("databind.toml");
+        let settings = if config_path.is_file() {
+            Rc::new(self.apply_config(&parent, dir, &config_path)?)
+        } else {
+            parent
+        };
+
+        self.cache.insert(dir.into(), settings.clone());
+        Ok(settings)
+    }
+
+    /// Apply a directory's `databind.toml` to the settings of its parent
+    fn apply_config(
+        &self,
+        parent: &DirSettings,
+        dir: &Path,
+        config_path: &Path,
+    ) -> std::io::Result<DirSettings> {
+        let relative = dir.strip_prefix(&self.root_dir).unwrap();
+        let relative = relative.to_str().unwrap().replace('\\', "/");
+
+        let contents = fs::read_to_string(config_path)?;
+        let config = crate::check_config(Settings::parse_config(&contents, config_path));
+
+        let mut settings = parent.settings.clone();
+        if let Err(message) =
+            settings.apply_dir_config(config, &relative, self.matches.value_of("profile"))
+        {
+            eprintln!("error: {}", message);
+            std::process::exit(1);
+        }
+
+        let location = if settings.namespace.is_some() || settings.subfolder.is_some() {
+            Some(self.location(parent, dir, &settings))
+        } else {
+            parent.location.clone()
+        };
+
+        Ok(DirSettings::new(
+            settings,
+            self.datapack,
+            &self.builtin_vars,
+            self.matches,
+            location,
+        ))
+    }
+
+    /// Get where the functions in a directory that sets
+    /// `namespace` or `subfolder` are put
+    fn location(&self, parent: &DirSettings, dir: &Path, settings: &Settings) -> Location {
+        let relative = dir.strip_prefix(&self.src_dir).unwrap();
+        let folders: Vec<_> = relative
+            .components()
+            .filter_map(|x| match x {
+                Component::Normal(x) => x.to_str(),
+                _ => None,
+            })
+            .collect();
+
+        // The folders before `namespace/functions`
+        let data_dir = match folders
+            .iter()
+            .position(|x| *x == "functions" || *x == "function")
+        {
+            Some(i) if i > 0 => folders[..i - 1].join("/"),
+            _ => {
+                eprintln!(
+                    "error: The namespace and subfolder settings can only be used inside of a functions folder, but were set in {}",
+                    dir.join("databind.toml").display()
+                );
+                std::process::exit(1);
+            }
+        };
+
+        // Anything that isn't set is kept from the parent
+        let config_path = dir.join("databind.toml");
+        let namespace = match &settings.namespace {
+            Some(namespace) => namespace.clone(),
+            None => parent.namespace(&config_path).unwrap(),
+        };
+        let prefix = match &settings.subfolder {
+            Some(subfolder) => match subfolder.trim_matches('/') {
+                "" => String::new(),
+                subfolder => format!("{}/", subfolder),
+            },
+            None => parent.subfolder(&config_path),
+        };
+
+        Location {
+            dir: dir.into(),
+            data_dir,
+            namespace,
+            prefix,
+        }
+    }
+}
+
+/// Check whether a file is one of the files matched by some globs
+fn matches_any(globs: &[PathBuf], path: &Path) -> bool {
+    globs
+        .iter()
+        .any(|file| is_same_file(file, path).expect("Failed to check file paths"))
+}
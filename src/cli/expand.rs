@@ -0,0 +1,154 @@
+/*
+ * Databind - Expand the functionality of Minecraft Datapacks.
+ * Copyright (C) 2021  Adam Thompson-Sharpe
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use crate::dir_config::DirConfigs;
+use databind::{
+    compiler::{consts::Consts, macros::Macros, Compiler},
+    files, Settings,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A file of the project with its settings applied
+struct Source {
+    path: PathBuf,
+    namespace: Option<String>,
+    contents: String,
+}
+
+/// Read a file, removing disabled conditional compilation blocks
+/// and replacing the variables from `vars.toml`
+fn read_source(path: PathBuf, dir_configs: &mut DirConfigs) -> std::io::Result<Source> {
+    let dir = dir_configs.for_file(&path)?;
+    let namespace = dir.namespace(&path);
+    let contents = fs::read_to_string(&path)?;
+    let vars = files::file_vars(&dir.vars, namespace.as_deref());
+    let contents = Compiler::apply_cfg(&contents, &dir.settings.features, &vars)
+        .and_then(|contents| Compiler::substitute_vars(&contents, &vars));
+    match contents {
+        Ok(contents) => Ok(Source {
+            path,
+            namespace,
+            contents,
+        }),
+        Err(compile_error) => crate::print_compile_error(&compile_error, &path),
+    }
+}
+
+/// Print a file after expanding its macros
+///
+/// Macros from the other files in the file's project can be used,
+/// and the project's config and `vars.toml` are used if found
+pub(crate) fn expand(args: clap::ArgMatches) -> std::io::Result<()> {
+    let path = Path::new(args.value_of("file").unwrap()).canonicalize()?;
+    let file_dir = path.parent().unwrap().to_path_buf();
+
+    // The project is the folder that databind.toml is in, if there is one.
+    // Subdirectories can have their own, so the outermost one is used
+    let mut config = files::find_config_in_parents(&file_dir, "databind.toml".into()).ok();
+    while let Some(parent) = config.as_ref().and_then(|x| x.parent()?.parent()) {
+        match files::find_config_in_parents(&parent, "databind.toml".into()) {
+            Ok(parent_config) => config = Some(parent_config),
+            Err(_) => break,
+        }
+    }
+    let (datapack, settings) = match &config {
+        Some(config) => {
+            let mut settings = crate::load_settings(config);
+            crate::apply_cli_settings(&mut settings, &args);
+            (config.parent().unwrap(), settings)
+        }
+        None => {
+            let mut settings = Settings::default();
+            crate::apply_cli_settings(&mut settings, &args);
+            (file_dir.as_path(), settings)
+        }
+    };
+    let datapack = datapack.to_str().unwrap();
+
+    let mut macros = Macros::new(settings.max_macro_depth);
+    macros.import_root = Some(datapack.into());
+    if let Err(message) = macros.load_std(&settings.std_macros) {
+        eprintln!("error: {}", message);
+        std::process::exit(1);
+    }
+
+    let src_dir = Path::new(datapack).join("src");
+    let src_dir = if src_dir.is_dir() {
+        src_dir
+    } else {
+        PathBuf::from(datapack)
+    };
+    let root_settings = settings.clone();
+    let builtin_vars = files::builtin_vars(datapack);
+    let mut dir_configs = DirConfigs::new(datapack, &src_dir, settings, builtin_vars, &args)?;
+
+    // Read the rest of the project so that its constants and macros can be used
+    let mut sources: Vec<Source> = vec![];
+    for other in files::merge_globs(&root_settings.inclusions, datapack)
+        .into_iter()
+        .chain(files::prioritize_macro_files(&src_dir))
+    {
+        let other = other.canonicalize()?;
+        let included = dir_configs.for_file(&other)?.is_included(&other);
+        if other == path || !included || sources.iter().any(|x| x.path == other) {
+            continue;
+        }
+
+        sources.push(read_source(other, &mut dir_configs)?);
+    }
+    // The file being expanded is last
+    sources.push(read_source(path, &mut dir_configs)?);
+
+    let mut consts = Consts::default();
+    for source in sources.iter() {
+        let file = source.path.display().to_string();
+        if let Err(compile_error) =
+            consts.collect(&source.contents, &file, source.namespace.as_deref())
+        {
+            crate::print_compile_error(&compile_error, &source.path);
+        }
+    }
+
+    let last = sources.len() - 1;
+    for (i, source) in sources.iter().enumerate() {
+        let file = source.path.display().to_string();
+        let contents = match consts.substitute(&source.contents, &file, source.namespace.as_deref())
+        {
+            Ok(contents) => contents,
+            Err(compile_error) => crate::print_compile_error(&compile_error, &source.path),
+        };
+
+        macros.start_file(Some(file));
+        if i < last {
+            if let Err(compile_error) = Compiler::collect_macros(&contents, &mut macros) {
+                crate::print_compile_error(&compile_error, &source.path);
+            }
+            continue;
+        }
+
+        macros.max_depth = dir_configs.for_file(&source.path)?.settings.max_macro_depth;
+        match Compiler::expand(&contents, &mut macros) {
+            Ok(expanded) => print!("{}", expanded),
+            Err(compile_error) => crate::print_compile_error(&compile_error, &source.path),
+        }
+    }
+
+    Ok(())
+}
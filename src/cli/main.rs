@@ -17,20 +17,28 @@
  */
 #![warn(clippy::all)]
 
+use clap::ArgMatches;
 use databind::{
-    compiler::{macros::Macro, Compiler},
-    files, Settings,
+    compiler::{consts::Consts, macros::Macros, parse::Rule, Compiler},
+    files,
+    symbols::SymbolTable,
+    version::Target,
+    Settings,
 };
-use pest::error::LineColLocation;
+use dir_config::DirConfigs;
+use pest::error::{Error, ErrorVariant, LineColLocation};
 use same_file::is_same_file;
 use std::{
     collections::HashMap,
     fs,
     path::{Path, PathBuf},
 };
+use toml::{value::Table, Value};
 
 mod cli;
 mod create_project;
+mod dir_config;
+mod expand;
 
 /// The main function
 ///
@@ -38,9 +46,12 @@ mod create_project;
 fn main() -> std::io::Result<()> {
     let matches = cli::get_matches();
 
-    // Check if create command is used
+    // Check if a subcommand is used
     if let Some(subcommand) = matches.subcommand {
-        return create_project::create_project(subcommand.matches);
+        return match subcommand.name.as_str() {
+            "expand" => expand::expand(subcommand.matches),
+            _ => create_project::create_project(subcommand.matches),
+        };
     }
 
     let datapack = matches.value_of("project").unwrap();
@@ -73,34 +84,31 @@ fn main() -> std::io::Result<()> {
 
     let mut compiler_settings: Settings;
     if config_path.exists() && !matches.is_present("ignore-config") {
-        let config_contents = fs::read_to_string(&config_path)?;
-        compiler_settings = toml::from_str(&config_contents[..]).unwrap();
+        compiler_settings = load_settings(config_path);
+        apply_cli_settings(&mut compiler_settings, &matches);
         compiler_settings.output = format!("{}/{}", datapack, compiler_settings.output);
-        let cli_out = matches.value_of("output").unwrap();
-        if cli_out != "out" {
-            compiler_settings.output = cli_out.into();
-        }
     } else {
         compiler_settings = Settings::default();
+        apply_cli_settings(&mut compiler_settings, &matches);
+    }
+    // An explicit `-o` overrides the config and `-D output=...`, even if it's the default
+    if matches.occurrences_of("output") > 0 {
         compiler_settings.output = matches.value_of("output").unwrap().into();
     }
 
     if datapack_is_dir {
         let mut tag_map: HashMap<String, Vec<String>> = HashMap::new();
-        let mut macros: HashMap<String, Macro> = HashMap::new();
-        let target_folder = &compiler_settings.output;
-
-        if fs::metadata(target_folder).is_ok() {
-            fs::remove_dir_all(&target_folder)?;
+        let mut macros = Macros::new(compiler_settings.max_macro_depth);
+        macros.import_root = Some(PathBuf::from(datapack));
+        if let Err(message) = macros.load_std(&compiler_settings.std_macros) {
+            eprintln!("error: {}", message);
+            std::process::exit(1);
+        }
+        // Used to check that every referenced function and tag exists
+        let mut symbols = SymbolTable::new();
+        if fs::metadata(&compiler_settings.output).is_ok() {
+            fs::remove_dir_all(&compiler_settings.output)?;
         }
-
-        let mut inclusions = files::merge_globs(&compiler_settings.inclusions, datapack);
-        let exclusions = files::merge_globs(&compiler_settings.exclusions, datapack);
-        inclusions = inclusions
-            .iter()
-            .filter(|&x| !exclusions.contains(x))
-            .cloned()
-            .collect();
 
         let src_dir = PathBuf::from(format!("{}/src", datapack));
         let src_dir = if !src_dir.exists() || !src_dir.is_dir() {
@@ -109,25 +117,97 @@ fn main() -> std::io::Result<()> {
             src_dir.as_path()
         };
 
-        // Read vars.toml if present
-        let vars = {
-            // Path to toml file
-            let path = format!("{}/vars.toml", datapack);
-            let vars_toml = Path::new(&path);
-            // Check if file exists
-            if vars_toml.exists() && vars_toml.is_file() {
-                Some(files::read_vars_toml(&vars_toml))
-            } else {
-                None
-            }
-        };
+        let target_folder = compiler_settings.output.clone();
+
+        // The version of Minecraft to compile for, if one is set
+        let target = compiler_settings.target().unwrap_or_else(|message| {
+            eprintln!("error: {}", message);
+            std::process::exit(1);
+        });
+        if let (Some(pack), Some(target)) = (&mut compiler_settings.pack, target) {
+            pack.pack_format.get_or_insert(target.pack_format);
+        }
+        let function_folder = target.map_or("functions", |x| x.function_folder());
+
+        // pack.mcmeta is generated from the [pack] table if there is one
+        let pack_mcmeta = compiler_settings.pack.as_ref().map(|pack| {
+            pack.to_mcmeta().unwrap_or_else(|message| {
+                eprintln!("error: {}", message);
+                std::process::exit(1);
+            })
+        });
+        let src_mcmeta = src_dir.join("pack.mcmeta");
+        if pack_mcmeta.is_some() && src_mcmeta.is_file() {
+            eprintln!(
+                "warning: {} is ignored because databind.toml has a [pack] table",
+                src_mcmeta.display()
+            );
+        }
+
+        let builtin_vars = files::builtin_vars(datapack);
+        let mut dir_configs =
+            DirConfigs::new(datapack, src_dir, compiler_settings, builtin_vars, &matches)?;
 
         // Get filepaths with global macros appearing first
         let paths = files::prioritize_macro_files(src_dir);
 
+        // Subdirectories can have their own databind.toml
+        let is_config = |path: &Path| {
+            (config_path.exists() && is_same_file(path, config_path).unwrap())
+                || path.file_name().is_some_and(|x| x == "databind.toml")
+        };
+
+        // Read files to compile and collect their constants and macros first
+        // so that they can be used before the file declaring them is compiled
+        let mut consts = Consts::default();
+        let mut sources: HashMap<PathBuf, String> = HashMap::new();
+        for path in paths.iter() {
+            let dir = dir_configs.for_file(path)?;
+            if is_config(path) || !dir.is_included(path) {
+                continue;
+            }
+
+            let file_contents = fs::read_to_string(path)
+                .unwrap_or_else(|_| panic!("Failed to read file {}", path.display()));
+            let namespace = dir.namespace(path);
+            let file_vars = files::file_vars(&dir.vars, namespace.as_deref());
+            let file_contents =
+                Compiler::apply_cfg(&file_contents, &dir.settings.features, &file_vars).and_then(
+                    |file_contents| Compiler::substitute_vars(&file_contents, &file_vars),
+                );
+            let file_contents = match file_contents {
+                Ok(file_contents) => file_contents,
+                Err(compile_error) => print_compile_error(&compile_error, path),
+            };
+
+            let file = path.canonicalize()?.display().to_string();
+            if let Err(compile_error) = consts.collect(&file_contents, &file, namespace.as_deref())
+            {
+                print_compile_error(&compile_error, path);
+            }
+            sources.insert(path.clone(), file_contents);
+        }
+
+        for path in paths.iter() {
+            if let Some(file_contents) = sources.get_mut(path) {
+                let file = path.canonicalize()?.display().to_string();
+                let namespace = dir_configs.for_file(path)?.namespace(path);
+                *file_contents = match consts.substitute(file_contents, &file, namespace.as_deref())
+                {
+                    Ok(file_contents) => file_contents,
+                    Err(compile_error) => print_compile_error(&compile_error, path),
+                };
+
+                macros.start_file(Some(file));
+                if let Err(compile_error) = Compiler::collect_macros(file_contents, &mut macros) {
+                    print_compile_error(&compile_error, path);
+                }
+            }
+        }
+
         for path in paths.iter() {
             // Do not add config file to output folder
-            if config_path.exists() && is_same_file(path, config_path).unwrap() {
+            if is_config(path) || pack_mcmeta.is_some() && path == &src_mcmeta {
                 continue;
             }
 
@@ -138,131 +218,69 @@ fn main() -> std::io::Result<()> {
 
             let relative_path = Path::new(&new_path_str);
 
-            let mut target_path: String = target_folder.to_string();
-            target_path.push('/');
-            target_path.push_str(relative_path.parent().unwrap().to_str().unwrap());
-
-            fs::create_dir_all(&target_path)?;
-
-            let mut compile = false;
-            let mut continue_loop = false;
-
-            for file in inclusions.iter() {
-                if is_same_file(file, path).expect("Failed to check file paths") {
-                    compile = true;
-                    break;
-                }
+            let dir = dir_configs.for_file(path)?;
+            let mut target_path = dir.target_dir(path, relative_path, &target_folder);
+            if target.is_some() {
+                target_path = files::rename_function_folders(&target_path, function_folder)
+                    .display()
+                    .to_string();
             }
 
-            for file in exclusions.iter() {
-                if is_same_file(file, path).expect("Failed to check file paths") {
-                    continue_loop = true;
-                    break;
-                }
-            }
+            fs::create_dir_all(&target_path)?;
 
-            if continue_loop {
+            if dir.is_excluded(path) {
                 continue;
             }
 
-            if compile {
-                let subfolder = files::get_subfolder_prefix(&path);
-                let file_contents = {
-                    let mut file_contents = fs::read_to_string(path)
-                        .expect(&format!("Failed to read file {}", path.display())[..]);
-                    if let Some(vars_map) = &vars {
-                        for (k, v) in vars_map.iter() {
-                            file_contents = file_contents.replace(k, v);
-                        }
-                    }
-                    file_contents
-                };
+            let subfolder = dir.subfolder(path);
+            let namespace = dir.namespace(path);
 
-                let compiled = Compiler::compile(
-                    &file_contents,
+            if let Some(file_contents) = sources.get(path) {
+                macros.start_file(Some(path.canonicalize()?.display().to_string()));
+                macros.max_depth = dir.settings.max_macro_depth;
+                let compiled = Compiler::compile_for(
+                    file_contents,
                     &subfolder,
-                    files::get_namespace(path).ok(),
+                    namespace.as_deref(),
+                    target,
                     &mut macros,
                 );
 
-                if let Err(compile_error) = compiled {
-                    let canonical_path = path.canonicalize().unwrap();
-                    let line = compile_error.line();
-
-                    let (row, col) = match compile_error.line_col {
-                        LineColLocation::Pos((row, col)) | LineColLocation::Span((row, col), _) => {
-                            (row, col)
-                        }
-                    };
-
-                    let line_highlighted = if !line.is_empty() {
-                        let problem = &line[col..].split(' ').next().unwrap();
-                        let mut highlight_text = String::new();
-
-                        for _ in 0..(col - 1) {
-                            highlight_text += " ";
-                        }
-
-                        for _ in 0..(problem.len() + 1) {
-                            highlight_text += "^";
-                        }
-
-                        Some(highlight_text)
-                    } else {
-                        None
-                    };
-
-                    let base_error = format!(
-                        "error: Unknown parsing error at {}:{}:{}",
-                        canonical_path.display(),
-                        row,
-                        col,
-                    );
-
-                    if let Some(line_highlighted) = line_highlighted {
-                        eprintln!(
-                            "{}\nProblem line:\n{}\n{}",
-                            base_error,
-                            compile_error.line(),
-                            line_highlighted,
-                        );
-                    } else {
-                        eprintln!("{}\nMaybe there's a missing `end`?", base_error);
-                    }
-
-                    std::process::exit(1);
+                let mut compiled = match compiled {
+                    Ok(compiled) => compiled,
+                    Err(compile_error) => print_compile_error(&compile_error, path),
                 };
-
-                let mut compiled = compiled.unwrap();
+                let namespace = namespace.unwrap();
+                let header = header(
+                    &dir.settings,
+                    &files::file_vars(&dir.vars, Some(&namespace)),
+                );
 
                 for (file, compiled_contents) in compiled.files.iter() {
                     if file.is_empty() {
                         continue;
                     }
 
+                    let function = format!("{}:{}{}", namespace, subfolder, file);
+                    warn_unavailable_commands(target, compiled_contents, &function);
+                    symbols.add_function(function);
+                    symbols.add_references(compiled_contents, path);
+
                     let full_path = format!("{}/{}.mcfunction", target_path, file);
 
-                    fs::write(full_path, compiled_contents)?;
+                    fs::write(full_path, format!("{}{}", header, compiled_contents))?;
 
                     // Add namespace prefix to function in tag map
                     for (_, funcs) in compiled.tags.iter_mut() {
                         if funcs.contains(file) {
                             let i = funcs.iter().position(|x| x == file).unwrap();
-                            funcs[i] = format!(
-                                "{}:{}{}",
-                                files::get_namespace(&path).unwrap(),
-                                &subfolder,
-                                file
-                            );
+                            funcs[i] = format!("{}:{}{}", namespace, &subfolder, file);
                         }
                     }
                 }
 
                 for (key, value) in compiled.tags {
-                    tag_map
-                        .entry(key)
-                        .or_insert(Vec::new())
-                        .append(&mut value.clone());
+                    tag_map.entry(key).or_default().append(&mut value.clone());
                 }
             } else {
                 let filename = relative_path.file_name().unwrap().to_str().unwrap();
@@ -270,12 +288,51 @@ fn main() -> std::io::Result<()> {
                 // Only copy if the file doesn't exist yet
                 // Intended to stop overwriting of Databind tags
                 if fs::metadata(&full_path).is_err() {
-                    fs::copy(&path, &full_path)?;
+                    fs::copy(path, &full_path)?;
+                }
+
+                if path.extension().is_some_and(|x| x == "mcfunction") {
+                    let function = format!(
+                        "{}:{}{}",
+                        namespace.unwrap(),
+                        subfolder,
+                        path.file_stem().unwrap().to_str().unwrap()
+                    );
+                    let contents = fs::read_to_string(path)?;
+                    warn_unavailable_commands(target, &contents, &function);
+                    symbols.add_function(function);
+                    symbols.add_references(&contents, path);
+                } else if let Some(tag) = files::get_tag_name(&path) {
+                    symbols.add_tag(tag);
                 }
             }
         }
 
-        files::create_tag_files(src_dir, Path::new(&target_folder), &tag_map)?;
+        // Tags from Databind code are always in the minecraft namespace
+        for tag in tag_map.keys() {
+            symbols.add_tag(format!("minecraft:{}", tag));
+        }
+
+        let unresolved = symbols.unresolved();
+        for reference in unresolved.iter() {
+            eprintln!("error: {}", reference);
+        }
+
+        if !unresolved.is_empty() {
+            std::process::exit(1);
+        }
+
+        files::create_tag_files(
+            src_dir,
+            Path::new(&target_folder),
+            &tag_map,
+            function_folder,
+        )?;
+
+        if let Some(pack_mcmeta) = pack_mcmeta {
+            fs::create_dir_all(&target_folder)?;
+            fs::write(Path::new(&target_folder).join("pack.mcmeta"), pack_mcmeta)?;
+        }
     } else {
         eprintln!("Databind does not support single-file compilation.");
         std::process::exit(1);
@@ -283,3 +340,197 @@ fn main() -> std::io::Result<()> {
 
     Ok(())
 }
+
+/// Read a config file, printing its warnings and exiting if it's invalid
+pub(crate) fn load_settings(path: &Path) -> Settings {
+    check_config(Settings::load(path))
+}
+
+/// Print the warnings from loading a config, or exit if it failed to load
+pub(crate) fn check_config<T>(loaded: Result<(T, Vec<String>), String>) -> T {
+    match loaded {
+        Ok((config, warnings)) => {
+            for warning in warnings {
+                eprintln!("warning: {}", warning);
+            }
+            config
+        }
+        Err(message) => {
+            eprintln!("error: {}", message);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Apply the profile selected with `--profile`, if any, then the settings
+/// from `-D`, and add the features from `--features`
+pub(crate) fn apply_cli_settings(settings: &mut Settings, matches: &ArgMatches) {
+    if let Some(profile) = matches.value_of("profile") {
+        if let Err(message) = settings.apply_profile(profile) {
+            eprintln!("error: {}", message);
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(message) = settings.apply_defines(&defines(matches)) {
+        eprintln!("error: {}", message);
+        std::process::exit(1);
+    }
+
+    if let Some(features) = matches.values_of("features") {
+        settings
+            .features
+            .extend(features.map(|x| x.trim().to_string()));
+    }
+}
+
+/// Get the global variables of a project from the built-in variables
+/// (using `[pack]` and `minecraft_version` if they're set), then its `vars.toml`, then the `vars` setting, then `-D` arguments
+///
+/// `builtin_vars` are from `files::builtin_vars`, which is only called once
+/// so that every directory gets the same `&build_time`
+pub(crate) fn load_vars(
+    builtin_vars: &HashMap<String, String>,
+    datapack: &str,
+    settings: &Settings,
+    matches: &ArgMatches,
+) -> HashMap<String, String> {
+    let mut vars = builtin_vars.clone();
+    if let Some(name) = settings.pack.as_ref().and_then(|x| x.name.as_ref()) {
+        vars.insert("&pack_name".into(), name.clone());
+    }
+    if let Some(version) = &settings.minecraft_version {
+        vars.insert("&minecraft_version".into(), version.clone());
+    }
+    if let Ok(Some(target)) = settings.target() {
+        vars.insert("&pack_format".into(), target.pack_format.to_string());
+    }
+    let vars_toml = Path::new(datapack).join("vars.toml");
+    if vars_toml.is_file() {
+        let vars_toml = files::read_vars_toml(&vars_toml).unwrap_or_else(|message| {
+            eprintln!("error: {}", message);
+            std::process::exit(1);
+        });
+        vars.extend(vars_toml);
+    }
+
+    files::override_vars(&mut vars, &settings.vars);
+
+    // -D arguments for settings are applied to the settings instead
+    let defines: HashMap<String, Value> = defines(matches)
+        .into_iter()
+        .filter(|(key, _)| !Settings::is_define_setting(key))
+        .collect();
+    files::override_vars(&mut vars, &defines);
+
+    vars
+}
+
+/// Get the keys and values from `-D key=value` arguments
+fn defines(matches: &ArgMatches) -> Table {
+    let mut defines = Table::new();
+    for define in matches.values_of("define").into_iter().flatten() {
+        let (key, value) = match define.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => (key.trim(), value),
+            _ => {
+                eprintln!(
+                    "error: Expected -D to be in the form key=value, got `{}`",
+                    define
+                );
+                std::process::exit(1);
+            }
+        };
+        // Values are read as TOML if possible so that `-D debug=true` is a boolean
+        let value = toml::from_str::<HashMap<String, Value>>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut x| x.remove("value"))
+            .unwrap_or_else(|| Value::String(value.into()));
+        defines.insert(key.into(), value);
+    }
+    defines
+}
+
+/// Print warnings for the commands in a function that aren't
+/// available in the target version of Minecraft
+fn warn_unavailable_commands(target: Option<Target>, contents: &str, function: &str) {
+    if let Some(target) = target {
+        for warning in target.check_commands(contents, function) {
+            eprintln!("warning: {}", warning);
+        }
+    }
+}
+
+/// Get the header to put at the start of a compiled function
+fn header(settings: &Settings, vars: &HashMap<String, String>) -> String {
+    if settings.header.is_empty() {
+        return String::new();
+    }
+
+    match Compiler::substitute_text_vars(&settings.header, vars) {
+        Ok(header) => format!("{}\n", header.trim_end()),
+        Err(error) => {
+            eprintln!("error: {} in the header setting", error.variant.message());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Print an error from compiling a file and exit
+fn print_compile_error(compile_error: &Error<Rule>, path: &Path) -> ! {
+    let canonical_path = path.canonicalize().unwrap();
+    let line = compile_error.line();
+
+    let (row, col) = match compile_error.line_col {
+        LineColLocation::Pos((row, col)) | LineColLocation::Span((row, col), _) => (row, col),
+    };
+
+    let line_highlighted = if !line.is_empty() {
+        let problem = &line[col..].split(' ').next().unwrap();
+        let mut highlight_text = String::new();
+
+        for _ in 0..(col - 1) {
+            highlight_text += " ";
+        }
+
+        for _ in 0..(problem.len() + 1) {
+            highlight_text += "^";
+        }
+
+        Some(highlight_text)
+    } else {
+        None
+    };
+
+    let message = match &compile_error.variant {
+        ErrorVariant::CustomError { message } => message.as_str(),
+        _ => "Unknown parsing error",
+    };
+
+    // Notes on the lines after the message are shown after the location
+    let (message, notes) = match message.split_once('\n') {
+        Some((message, notes)) => (message, format!("\n{}", notes)),
+        None => (message, String::new()),
+    };
+
+    let base_error = format!(
+        "error: {} at {}:{}:{}{}",
+        message,
+        canonical_path.display(),
+        row,
+        col,
+        notes,
+    );
+
+    if let Some(line_highlighted) = line_highlighted {
+        eprintln!(
+            "{}\nProblem line:\n{}\n{}",
+            base_error,
+            compile_error.line(),
+            line_highlighted,
+        );
+    } else {
+        eprintln!("{}\nMaybe there's a missing `end`?", base_error);
+    }
+
+    std::process::exit(1);
+}
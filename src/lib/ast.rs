@@ -24,6 +24,13 @@ pub enum AssignmentOp {
     Set,
 }
 
+/// A value passed to or returned from a function
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Integer(i32),
+    Var(String),
+}
+
 /// The main enum for Databind's AST
 #[derive(Clone, Debug, PartialEq)]
 pub enum Node {
@@ -57,7 +64,15 @@ pub enum Node {
         contents: Vec<Node>,
     },
     Tag(String),
-    CallFunction(String),
+    CallFunction {
+        name: String,
+        args: Vec<Value>,
+    },
+    Return(Value),
+    /// Ends a function with the `return` command if the target version has it.
+    /// Added after the value of a `return` at the end of a function is stored
+    EndFunction(Value),
+    ReturnValue(String),
     IfStatement {
         condition: Vec<Node>,
         if_block: Vec<Node>,
@@ -0,0 +1,276 @@
+/*
+ * Databind - Expand the functionality of Minecraft Datapacks.
+ * Copyright (C) 2021  Adam Thompson-Sharpe
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Conditional compilation with `!ifdef`, `!ifndef`, and `!cfg(...)` blocks
+use super::{parse::ParseResult, vars::name_len, Compiler};
+use pest::{
+    error::{Error, ErrorVariant},
+    Position,
+};
+use std::collections::HashMap;
+
+/// A block that's open while going through a file
+enum Block {
+    /// A conditional compilation block
+    Cfg {
+        /// Whether the lines in the current branch of the block are kept
+        active: bool,
+        has_else: bool,
+        /// The directive that started the block and where it is
+        directive: &'static str,
+        offset: usize,
+    },
+    /// A macro's `!if` block, which is left for the macro to evaluate
+    MacroIf,
+}
+
+/// What conditions are checked against
+struct Context<'a> {
+    features: &'a [String],
+    vars: &'a HashMap<String, String>,
+}
+
+impl Context<'_> {
+    /// Check whether a name is defined. Names are defined if they're an enabled feature
+    /// or a global variable that isn't false, `0`, or empty
+    fn is_defined(&self, name: &str) -> bool {
+        self.features.iter().any(|x| x == name)
+            || self
+                .vars
+                .get(&format!("&{}", name))
+                .is_some_and(|x| !matches!(x.as_str(), "" | "0" | "false"))
+    }
+
+    /// Evaluate the condition of a `!cfg(...)` block
+    fn eval(&self, condition: &str) -> Result<bool, String> {
+        let condition = condition.trim();
+
+        if let Some(args) = call(condition, "not") {
+            let args = split_args(args);
+            if args.len() != 1 {
+                return Err(format!(
+                    "Expected 1 condition in `not(...)`, got {}",
+                    args.len()
+                ));
+            }
+            return Ok(!self.eval(args[0])?);
+        }
+        if let Some(args) = call(condition, "all") {
+            return split_args(args)
+                .iter()
+                .try_fold(true, |all, x| Ok(self.eval(x)? && all));
+        }
+        if let Some(args) = call(condition, "any") {
+            return split_args(args)
+                .iter()
+                .try_fold(false, |any, x| Ok(self.eval(x)? || any));
+        }
+
+        if let Some((name, value)) = condition.split_once('=') {
+            let name = name.trim();
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|x| x.strip_suffix('"'))
+                .unwrap_or(value);
+
+            if name == "feature" {
+                return Ok(self.features.iter().any(|x| x == value));
+            }
+            if is_name(name) {
+                return Ok(self.vars.get(&format!("&{}", name)).map(|x| x.as_str()) == Some(value));
+            }
+        } else if is_name(condition) {
+            return Ok(self.is_defined(condition));
+        }
+
+        Err(format!("Invalid condition `{}` in `!cfg`", condition))
+    }
+}
+
+impl Compiler {
+    /// Remove the lines of `!ifdef NAME`, `!ifndef NAME`, and `!cfg(...)` blocks
+    /// whose conditions are false, along with the directives themselves.
+    /// Blocks end with `!endif` and can have an `!else`
+    ///
+    /// Removed lines are replaced with empty lines so that line numbers don't change.
+    /// Macros' `!if` blocks are left alone
+    ///
+    /// # Arguments
+    ///
+    /// - `raw` - The contents of the file
+    /// - `features` - The features enabled with `--features` or the `features` setting
+    /// - `vars` - The global variables, with keys starting with `&`
+    pub fn apply_cfg(
+        raw: &str,
+        features: &[String],
+        vars: &HashMap<String, String>,
+    ) -> ParseResult<String> {
+        let context = Context { features, vars };
+        let mut output = String::with_capacity(raw.len());
+        let mut stack: Vec<Block> = vec![];
+        let mut offset = 0;
+
+        for line in raw.split_inclusive('\n') {
+            let trimmed = line.trim();
+            let start = offset + line.len() - line.trim_start().len();
+            let error = |message: String| {
+                Box::new(Error::new_from_pos(
+                    ErrorVariant::CustomError { message },
+                    Position::new(raw, start).unwrap(),
+                ))
+            };
+
+            let active = stack.iter().all(|x| match x {
+                Block::Cfg { active, .. } => *active,
+                Block::MacroIf => true,
+            });
+            let (directive, rest) = match trimmed.find(|c: char| c.is_whitespace() || c == '(') {
+                Some(i) => (&trimmed[..i], trimmed[i..].trim()),
+                None => (trimmed, ""),
+            };
+
+            let keep = match directive {
+                "!ifdef" | "!ifndef" => {
+                    if !is_name(rest) {
+                        return Err(error(format!("Expected a name after `{}`", directive)));
+                    }
+                    let defined = context.is_defined(rest);
+                    stack.push(Block::Cfg {
+                        active: if directive == "!ifdef" {
+                            defined
+                        } else {
+                            !defined
+                        },
+                        has_else: false,
+                        directive: if directive == "!ifdef" {
+                            "!ifdef"
+                        } else {
+                            "!ifndef"
+                        },
+                        offset: start,
+                    });
+                    false
+                }
+                "!cfg" => {
+                    let condition = rest
+                        .strip_prefix('(')
+                        .and_then(|x| x.strip_suffix(')'))
+                        .ok_or_else(|| {
+                            error("Expected a condition like `!cfg(feature = \"x\")`".into())
+                        })?;
+                    stack.push(Block::Cfg {
+                        active: context.eval(condition).map_err(error)?,
+                        has_else: false,
+                        directive: "!cfg",
+                        offset: start,
+                    });
+                    false
+                }
+                "!if" => {
+                    stack.push(Block::MacroIf);
+                    active
+                }
+                "!else" => match stack.last_mut() {
+                    Some(Block::Cfg {
+                        active, has_else, ..
+                    }) => {
+                        if *has_else {
+                            return Err(error("Found a second `!else` in a block".into()));
+                        }
+                        *active = !*active;
+                        *has_else = true;
+                        false
+                    }
+                    Some(Block::MacroIf) => active,
+                    None => return Err(error("Found `!else` outside of a block".into())),
+                },
+                "!endif" => match stack.pop() {
+                    Some(Block::Cfg { .. }) => false,
+                    Some(Block::MacroIf) => active,
+                    None => return Err(error("Found `!endif` without a block to end".into())),
+                },
+                _ => active,
+            };
+
+            if keep {
+                output.push_str(line);
+            } else {
+                output.push_str(&line[line.trim_end_matches(&['\r', '\n'][..]).len()..]);
+            }
+            offset += line.len();
+        }
+
+        if let Some(Block::Cfg {
+            directive, offset, ..
+        }) = stack.into_iter().find(|x| matches!(x, Block::Cfg { .. }))
+        {
+            return Err(Box::new(Error::new_from_pos(
+                ErrorVariant::CustomError {
+                    message: format!(
+                        "Unclosed `{}` block\nhelp: add an `!endif` to end it",
+                        directive
+                    ),
+                },
+                Position::new(raw, offset).unwrap(),
+            )));
+        }
+
+        Ok(output)
+    }
+}
+
+/// Check if some text is a name
+fn is_name(text: &str) -> bool {
+    !text.is_empty() && name_len(text) == text.len()
+}
+
+/// Get the arguments of a call in a condition (eg. `a, b` for `all(a, b)`)
+fn call<'a>(condition: &'a str, name: &str) -> Option<&'a str> {
+    condition
+        .strip_prefix(name)?
+        .trim_start()
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+/// Split the arguments of a call by commas that aren't in parentheses or quotes
+fn split_args(args: &str) -> Vec<&str> {
+    let mut split: Vec<&str> = vec![];
+    let mut depth = 0;
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (i, c) in args.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '(' if !in_quotes => depth += 1,
+            ')' if !in_quotes => depth -= 1,
+            ',' if !in_quotes && depth == 0 => {
+                split.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    let last = args[start..].trim();
+    if !last.is_empty() {
+        split.push(last);
+    }
+    split
+}
@@ -15,8 +15,11 @@
  * You should have received a copy of the GNU General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
-use super::{macros::Macro, parse::ParseResult, Compiler};
-use crate::ast::{AssignmentOp, Node};
+use super::{macros::Macros, parse::ParseResult, Compiler};
+use crate::{
+    ast::{AssignmentOp, Node, Value},
+    version::Target,
+};
 use std::collections::HashMap;
 
 #[derive(Clone, Debug)]
@@ -26,25 +29,32 @@ pub struct Compiled {
 }
 
 impl Compiler {
-    pub fn nodes_to_text(nodes: &Vec<Node>, namespace: Option<&str>) -> String {
+    pub fn nodes_to_text(
+        nodes: &[Node],
+        subfolder: &str,
+        namespace: Option<&str>,
+        target: Option<Target>,
+    ) -> String {
         Compiler::compile_ast(
             nodes,
             &mut HashMap::new(),
             &mut HashMap::new(),
             &mut vec![String::new()],
-            "",
+            subfolder,
             namespace,
-        )["".into()]
-        .clone()
+            target,
+        )[""]
+            .clone()
     }
 
     pub fn compile_ast<'a>(
-        ast: &Vec<Node>,
+        ast: &[Node],
         files: &'a mut HashMap<String, String>,
         tag_map: &'a mut HashMap<String, Vec<String>>,
         nested_funcs: &mut Vec<String>,
         subfolder: &str,
         namespace: Option<&str>,
+        target: Option<Target>,
     ) -> &'a mut HashMap<String, String> {
         if files.is_empty() {
             files.insert(String::new(), String::new());
@@ -128,45 +138,105 @@ impl Compiler {
                     nested_funcs.push(name.clone());
                     files.insert(name.clone(), String::new());
                     Compiler::compile_ast(
-                        &contents,
+                        contents,
                         files,
                         tag_map,
                         nested_funcs,
                         subfolder,
                         namespace,
+                        target,
                     );
                     nested_funcs.pop();
                 }
 
                 Node::Tag(tag) => tag_map
                     .entry(tag.clone())
-                    .or_insert(Vec::new())
+                    .or_default()
                     .push(current_func!().clone()),
 
-                Node::CallFunction(name) => {
-                    // Function contains namespace
-                    let has_namespace = name.contains(':');
+                Node::CallFunction { name, args } => {
+                    let function = Compiler::resolve_function(name, namespace);
 
-                    if has_namespace {
-                        current_file!().push_str(&format!("function {}\n", name));
-                    } else if let Some(ns) = namespace {
-                        current_file!().push_str(&format!("function {}:{}\n", ns, name));
-                    } else {
-                        panic!("internal: no namespace provided for function call");
+                    // Copy arguments into the function's parameters
+                    let mut setup = vec![];
+                    for (i, arg) in args.iter().enumerate() {
+                        let objective = Compiler::function_objective(&function, &i.to_string());
+                        setup.append(&mut Compiler::store_value(&objective, arg));
                     }
+                    Compiler::compile_ast(
+                        &setup,
+                        files,
+                        tag_map,
+                        nested_funcs,
+                        subfolder,
+                        namespace,
+                        target,
+                    );
+
+                    current_file!().push_str(&format!("function {}\n", function));
                 }
 
-                Node::MinecraftCommand { name, args } => {
+                // Return statements are lowered when their function is parsed,
+                // and ones outside of a function are rejected by the parser
+                Node::Return(_) => {}
+
+                Node::EndFunction(value) => match (target, value) {
+                    (Some(target), Value::Integer(value)) if target.supports_return() => {
+                        current_file!().push_str(&format!("return {}\n", value));
+                    }
+                    (Some(target), Value::Var(name)) if target.supports_return_run() => {
+                        current_file!().push_str(&format!(
+                            "return run scoreboard players get --databind {}\n",
+                            name
+                        ));
+                    }
+                    _ => {}
+                },
+
+                Node::ReturnValue(name) => {
+                    let function = Compiler::resolve_function(name, namespace);
                     current_file!().push_str(&format!(
-                        "{}{}\n",
-                        name,
-                        Compiler::nodes_to_text(&args, namespace)
+                        "--databind {} ",
+                        Compiler::function_objective(&function, "ret")
                     ));
                 }
 
+                Node::MinecraftCommand { name, args } => {
+                    let mut setup = String::new();
+                    let mut command = name.clone();
+
+                    // Compile each argument separately so that nodes which
+                    // normally end a line (eg. function calls) are joined
+                    // with single spaces instead
+                    for arg in args {
+                        let text = Compiler::nodes_to_text(
+                            std::slice::from_ref(arg),
+                            subfolder,
+                            namespace,
+                            target,
+                        );
+                        let mut lines: Vec<&str> = text.trim().lines().collect();
+                        let text = lines.pop().unwrap_or("").trim();
+
+                        // Commands needed before the argument (eg. setting the
+                        // parameters of a called function) go before the command
+                        for line in lines {
+                            setup.push_str(line.trim());
+                            setup.push('\n');
+                        }
+
+                        if !text.is_empty() {
+                            command.push(' ');
+                            command.push_str(text);
+                        }
+                    }
+
+                    current_file!().push_str(&format!("{}{}\n", setup, command));
+                }
+
                 Node::CommandArg(arg) => current_file!().push_str(&format!(" {}", arg)),
 
-                Node::TrustMe(content) => current_file!().push_str(&content),
+                Node::TrustMe(content) => current_file!().push_str(content),
 
                 Node::IfStatement { .. }
                 | Node::WhileLoop { .. }
@@ -180,11 +250,160 @@ impl Compiler {
         files
     }
 
+    /// Get the full name of a called function, including its namespace
+    pub(crate) fn resolve_function(name: &str, namespace: Option<&str>) -> String {
+        // Function contains namespace
+        if name.contains(':') {
+            name.into()
+        } else if let Some(ns) = namespace {
+            format!("{}:{}", ns, name)
+        } else {
+            panic!("internal: no namespace provided for function call");
+        }
+    }
+
+    /// Get the name of the objective used for one of a function's parameters
+    /// or for its return value (eg. `cmd.damage.0` or `cmd.damage.ret`)
+    ///
+    /// # Arguments
+    ///
+    /// - `function` - The path to the function, with or without a namespace
+    /// - `slot` - The index of the parameter, or `ret` for the return value
+    pub(crate) fn function_objective(function: &str, slot: &str) -> String {
+        // Objective names can't contain a `:` or `/`
+        let path = function.rsplit(':').next().unwrap();
+        format!("{}.{}", path.replace('/', "."), slot)
+    }
+
+    /// Get the nodes needed to store a value in a variable
+    pub(crate) fn store_value(name: &str, value: &Value) -> Vec<Node> {
+        match value {
+            Value::Integer(value) => vec![Node::NewVar {
+                name: name.into(),
+                value: *value,
+            }],
+            Value::Var(var) => vec![
+                Node::NewObjective {
+                    name: name.into(),
+                    objective: "dummy".into(),
+                },
+                Node::MinecraftCommand {
+                    name: "scoreboard".into(),
+                    args: [
+                        "players",
+                        "operation",
+                        "--databind",
+                        name,
+                        "=",
+                        "--databind",
+                        var,
+                    ]
+                    .iter()
+                    .map(|x| Node::CommandArg(x.to_string()))
+                    .collect(),
+                },
+            ],
+        }
+    }
+
+    /// Replace references to a function's parameters with the objectives
+    /// that store them, and lower `return` statements to scoreboard commands
+    ///
+    /// # Arguments
+    ///
+    /// - `nodes` - The contents of the function
+    /// - `function` - The path to the function (eg. `cmd/damage`)
+    /// - `params` - The names of the function's parameters
+    /// - `top_level` - Whether the nodes are directly in the function instead of in a block.
+    ///   A `return` at the end of the function also uses the `return` command when the
+    ///   target version has it, so that the function's result is the returned value
+    pub(crate) fn lower_function_body(
+        nodes: &[Node],
+        function: &str,
+        params: &[String],
+        top_level: bool,
+    ) -> Vec<Node> {
+        let rename = |name: &String| match params.iter().position(|x| x == name) {
+            Some(i) => Compiler::function_objective(function, &i.to_string()),
+            None => name.clone(),
+        };
+
+        let rename_value = |value: &Value| match value {
+            Value::Var(name) => Value::Var(rename(name)),
+            Value::Integer(_) => value.clone(),
+        };
+
+        let mut lowered = vec![];
+
+        for (i, node) in nodes.iter().enumerate() {
+            match node {
+                Node::NewVar { name, value } => lowered.push(Node::NewVar {
+                    name: rename(name),
+                    value: *value,
+                }),
+                Node::SetVar {
+                    name,
+                    operator,
+                    value,
+                } => lowered.push(Node::SetVar {
+                    name: rename(name),
+                    operator: operator.clone(),
+                    value: *value,
+                }),
+                Node::TestVar { name, test } => lowered.push(Node::TestVar {
+                    name: rename(name),
+                    test: test.clone(),
+                }),
+                Node::DeleteVar(name) => lowered.push(Node::DeleteVar(rename(name))),
+                Node::GetVar(name) => lowered.push(Node::GetVar(rename(name))),
+                Node::Function { name, contents } => lowered.push(Node::Function {
+                    name: name.clone(),
+                    contents: Compiler::lower_function_body(contents, function, params, false),
+                }),
+                Node::CallFunction { name, args } => lowered.push(Node::CallFunction {
+                    name: name.clone(),
+                    args: args.iter().map(rename_value).collect(),
+                }),
+                Node::MinecraftCommand { name, args } => lowered.push(Node::MinecraftCommand {
+                    name: name.clone(),
+                    args: Compiler::lower_function_body(args, function, params, false),
+                }),
+                Node::Return(value) => {
+                    let objective = Compiler::function_objective(function, "ret");
+                    lowered.append(&mut Compiler::store_value(&objective, &rename_value(value)));
+                    // The rest of the function always runs, so only the last
+                    // statement can use the `return` command
+                    if top_level && i == nodes.len() - 1 {
+                        lowered.push(Node::EndFunction(match value {
+                            Value::Integer(_) => value.clone(),
+                            Value::Var(_) => Value::Var(objective),
+                        }));
+                    }
+                }
+                _ => lowered.push(node.clone()),
+            }
+        }
+
+        lowered
+    }
+
     pub fn compile(
         raw_file: &str,
         subfolder: &str,
         namespace: Option<&str>,
-        macros: &mut HashMap<String, Macro>,
+        macros: &mut Macros,
+    ) -> ParseResult<Compiled> {
+        Compiler::compile_for(raw_file, subfolder, namespace, None, macros)
+    }
+
+    /// Compile a file for a version of Minecraft, using commands from newer versions
+    /// (eg. `return`) when they're available. Works like `compile` when `target` is `None`
+    pub fn compile_for(
+        raw_file: &str,
+        subfolder: &str,
+        namespace: Option<&str>,
+        target: Option<Target>,
+        macros: &mut Macros,
     ) -> ParseResult<Compiled> {
         let mut files: HashMap<String, String> = HashMap::new();
         let mut tags: HashMap<String, Vec<String>> = HashMap::new();
@@ -198,6 +417,7 @@ impl Compiler {
             &mut Vec::new(),
             subfolder,
             namespace,
+            target,
         );
 
         Ok(Compiled { files, tags })
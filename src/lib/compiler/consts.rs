@@ -0,0 +1,494 @@
+/*
+ * Databind - Expand the functionality of Minecraft Datapacks.
+ * Copyright (C) 2021  Adam Thompson-Sharpe
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Compile-time constants declared in source files with `const NAME = value`.
+//! Constant names are UPPER_CASE so they don't clash with words in commands
+use super::{
+    macros::Location,
+    parse::{ParseResult, Rule},
+    vars::name_len,
+};
+use pest::{
+    error::{Error, ErrorVariant},
+    Position,
+};
+use std::collections::HashMap;
+
+/// Words followed by the name of a function or tag, which is never a constant
+const NAME_KEYWORDS: &[&str] = &["func", "call", "retval", "tag"];
+
+/// A constant declared in a source file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Const {
+    /// The value as it was written, before it's folded
+    pub value: String,
+    /// Where the constant's name is
+    pub location: Location,
+    /// The namespace of the file declaring the constant
+    pub namespace: Option<String>,
+}
+
+/// Keeps track of the constants declared in a project
+///
+/// Constants declared with `const` can only be used in the file declaring them,
+/// and ones declared with `pub const` can be used in any file in the same namespace
+#[derive(Debug, Default)]
+pub struct Consts {
+    /// Constants declared with `pub const` by namespace
+    public: HashMap<Option<String>, HashMap<String, Const>>,
+    /// Constants declared with `const` by file
+    private: HashMap<String, HashMap<String, Const>>,
+}
+
+/// A constant declaration on a line
+struct Declaration<'a> {
+    public: bool,
+    name: &'a str,
+    value: &'a str,
+    /// Where the name starts in the line
+    column: usize,
+}
+
+impl Consts {
+    /// Collect the constants declared in a file
+    ///
+    /// # Arguments
+    ///
+    /// - `raw` - The contents of the file
+    /// - `file` - The path to the file
+    /// - `namespace` - The namespace of the file, if it has one
+    pub fn collect(&mut self, raw: &str, file: &str, namespace: Option<&str>) -> ParseResult<()> {
+        let mut offset = 0;
+
+        for (i, line) in raw.split_inclusive('\n').enumerate() {
+            if let Some(declaration) = parse_declaration(line) {
+                let declaration = declaration
+                    .map_err(|(column, message)| error_at(raw, offset + column, message))?;
+                let name = declaration.name;
+
+                let existing = self.get(name, file, namespace).or_else(|| {
+                    // A public constant can't have the same name as a private one
+                    // in another file of the namespace
+                    self.private
+                        .values()
+                        .filter_map(|x| x.get(name))
+                        .find(|x| declaration.public && x.namespace.as_deref() == namespace)
+                });
+                if let Some(existing) = existing {
+                    return Err(error_at(
+                        raw,
+                        offset + declaration.column,
+                        format!(
+                            "Constant {} is already defined\nnote: first defined at {}",
+                            name, existing.location
+                        ),
+                    ));
+                }
+
+                let constant = Const {
+                    value: declaration.value.into(),
+                    location: Location {
+                        file: Some(file.into()),
+                        line: i + 1,
+                        column: declaration.column + 1,
+                    },
+                    namespace: namespace.map(String::from),
+                };
+
+                if declaration.public {
+                    self.public
+                        .entry(namespace.map(String::from))
+                        .or_default()
+                        .insert(name.into(), constant);
+                } else {
+                    self.private
+                        .entry(file.into())
+                        .or_default()
+                        .insert(name.into(), constant);
+                }
+            }
+
+            offset += line.len();
+        }
+
+        Ok(())
+    }
+
+    /// Get a constant that can be used in a file
+    pub fn get(&self, name: &str, file: &str, namespace: Option<&str>) -> Option<&Const> {
+        self.private
+            .get(file)
+            .and_then(|x| x.get(name))
+            .or_else(|| {
+                self.public
+                    .get(&namespace.map(String::from))
+                    .and_then(|x| x.get(name))
+            })
+    }
+
+    /// Replace the constants used in a file with their values.
+    /// Declarations are replaced with empty lines so that line numbers don't change
+    ///
+    /// Constants are matched as whole names outside of quotes and comments.
+    /// Names that are part of something else, such as objectives (`stats.hp`),
+    /// NBT and selectors (`{hp:1b}`), or function names (`call hp`), are left alone.
+    /// `%NAME` is left as the text `NAME`
+    pub fn substitute(
+        &self,
+        raw: &str,
+        file: &str,
+        namespace: Option<&str>,
+    ) -> ParseResult<String> {
+        let mut replaced = String::with_capacity(raw.len());
+        let mut offset = 0;
+
+        for line in raw.split_inclusive('\n') {
+            if parse_declaration(line).is_some() {
+                replaced.push_str(&line[line.trim_end_matches(&['\r', '\n'][..]).len()..]);
+            } else if line.trim_start().starts_with('#') {
+                replaced.push_str(line);
+            } else {
+                let line = replace_names(line, true, |name| {
+                    self.fold(name, file, namespace, &mut vec![]).transpose()
+                })
+                .map_err(|(column, message)| error_at(raw, offset + column, message))?;
+                replaced.push_str(&line);
+            }
+
+            offset += line.len();
+        }
+
+        Ok(replaced)
+    }
+
+    /// Get the value of a constant, folding arithmetic (eg. `MAX_HP / 2`) into a number
+    fn fold(
+        &self,
+        name: &str,
+        file: &str,
+        namespace: Option<&str>,
+        stack: &mut Vec<String>,
+    ) -> Option<Result<String, String>> {
+        let constant = self.get(name, file, namespace)?;
+
+        if stack.iter().any(|x| x == name) {
+            stack.push(name.into());
+            return Some(Err(format!(
+                "Constant {} depends on itself ({})",
+                name,
+                stack.join(" -> ")
+            )));
+        }
+
+        stack.push(name.into());
+        let value = match unquote(&constant.value) {
+            Some(value) => Ok(value),
+            None => {
+                let file = constant.location.file.as_deref().unwrap_or_default();
+                replace_names(&constant.value, false, |x| {
+                    self.fold(x, file, constant.namespace.as_deref(), stack)
+                        .transpose()
+                })
+                .map_err(|(_, message)| message)
+                .and_then(|value| match eval_int(&value) {
+                    Some(result) => result.map(|x| x.to_string()),
+                    None => Ok(value),
+                })
+            }
+        };
+        stack.pop();
+
+        Some(value.map_err(|message| {
+            format!(
+                "{}\nnote: in constant {} defined at {}",
+                message, name, constant.location
+            )
+        }))
+    }
+}
+
+/// Create an error at a byte offset in a file
+fn error_at(raw: &str, offset: usize, message: String) -> Box<Error<Rule>> {
+    Box::new(Error::new_from_pos(
+        ErrorVariant::CustomError { message },
+        Position::new(raw, offset).unwrap(),
+    ))
+}
+
+/// Parse a line declaring a constant (eg. `pub const MAX_HP = 20`).
+/// Returns `None` if the line isn't a declaration
+fn parse_declaration(line: &str) -> Option<Result<Declaration<'_>, (usize, String)>> {
+    let trimmed = line.trim_start();
+    let (public, rest) = match trimmed.strip_prefix("pub ") {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, trimmed),
+    };
+    let rest = rest.strip_prefix("const")?;
+    if !rest.starts_with(&[' ', '\t'][..]) {
+        return None;
+    }
+
+    let rest = rest.trim_start();
+    let column = line.len() - rest.len();
+    let len = name_len(rest);
+    if len == 0 {
+        return Some(Err((column, "Expected a name for the constant".into())));
+    }
+
+    let name = &rest[..len];
+    // Names have to stand out so that words in commands aren't replaced
+    if name.chars().any(|c| c.is_ascii_lowercase()) {
+        return Some(Err((
+            column,
+            format!(
+                "Constant names must be UPPER_CASE\nhelp: rename {} to {}",
+                name,
+                name.to_ascii_uppercase()
+            ),
+        )));
+    }
+
+    let value = match rest[len..].trim_start().strip_prefix('=') {
+        Some(value) => value.trim(),
+        None => {
+            return Some(Err((
+                column,
+                format!("Expected `=` after constant {}", name),
+            )))
+        }
+    };
+    if value.is_empty() {
+        return Some(Err((
+            column,
+            format!("Expected a value for constant {}", name),
+        )));
+    }
+
+    Some(Ok(Declaration {
+        public,
+        name,
+        value,
+        column,
+    }))
+}
+
+/// Replace the names in some text that aren't in quotes or part of something else
+/// (eg. `$name` or `ns:name`). `replace` returns the value for a name, or `None`
+/// if it shouldn't be replaced. Errors include where the name that caused them is
+///
+/// In source code (`in_source`), names joined to other text with `.` or `-`, keys
+/// (eg. `hp` in `{hp:1b}` or `[hp=1]`), and function names are also left alone
+fn replace_names<F>(text: &str, in_source: bool, mut replace: F) -> Result<String, (usize, String)>
+where
+    F: FnMut(&str) -> Result<Option<String>, String>,
+{
+    let mut replaced = String::with_capacity(text.len());
+    // The end of the last replaced name
+    let mut last = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut i = 0;
+
+    while let Some(c) = text[i..].chars().next() {
+        if in_quotes || c == '"' {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = !in_quotes,
+                _ => {}
+            }
+            i += c.len_utf8();
+            continue;
+        }
+
+        let len = name_len(&text[i..]);
+        if len == 0 {
+            i += c.len_utf8();
+            continue;
+        }
+
+        let name = &text[i..i + len];
+        let previous = text[..i].chars().next_back();
+        let mut part_of_other =
+            previous.is_some_and(|x| x.is_ascii_alphanumeric() || "_$&?:/#".contains(x));
+
+        if in_source {
+            let (before, after) = (&text[..i], &text[i + len..]);
+            // Ranges (eg. `..MAX`) are values, but other dots join names (eg. `stats.hp`)
+            let dot_before = before.ends_with('.') && !before.ends_with("..");
+            let dot_after = after.starts_with('.') && !after.starts_with("..");
+            let previous_word = before.split_whitespace().next_back();
+            part_of_other = part_of_other
+                || dot_before
+                || dot_after
+                || before.ends_with(|x| "-{[".contains(x))
+                || after.starts_with(|x| "-:=[{".contains(x))
+                || previous_word.is_some_and(|x| NAME_KEYWORDS.contains(&x))
+                || text.split_whitespace().next() == Some("func");
+        }
+
+        if !part_of_other {
+            if let Some(value) = replace(name).map_err(|message| (i, message))? {
+                if previous == Some('%') {
+                    // Escaped, so only remove the %
+                    replaced.push_str(&text[last..i - 1]);
+                    last = i;
+                } else {
+                    replaced.push_str(&text[last..i]);
+                    replaced.push_str(&value);
+                    last = i + len;
+                }
+            }
+        }
+
+        i += len;
+    }
+
+    replaced.push_str(&text[last..]);
+    Ok(replaced)
+}
+
+/// Get the contents of a quoted value, if it's quoted
+fn unquote(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    Some(inner.replace("\\\"", "\"").replace("\\\\", "\\"))
+}
+
+/// Evaluate integer arithmetic (eg. `(MAX + 1) * 2` after replacing `MAX`).
+/// Returns `None` if the text isn't arithmetic
+fn eval_int(text: &str) -> Option<Result<i32, String>> {
+    let is_arithmetic = |c: char| c.is_ascii_digit() || c.is_whitespace() || "+-*/%()".contains(c);
+    if !text.chars().all(is_arithmetic) || !text.chars().any(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    // Split the text into numbers and single-character operators
+    let mut tokens: Vec<&str> = vec![];
+    let mut number_start: Option<usize> = None;
+    for (i, c) in text.char_indices() {
+        if c.is_ascii_digit() {
+            number_start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = number_start.take() {
+            tokens.push(&text[start..i]);
+        }
+        if !c.is_whitespace() {
+            tokens.push(&text[i..i + 1]);
+        }
+    }
+    if let Some(start) = number_start {
+        tokens.push(&text[start..]);
+    }
+
+    let mut parser = Arithmetic {
+        text,
+        tokens: &tokens,
+        pos: 0,
+    };
+    let result = parser.expr().and_then(|value| {
+        if parser.pos == tokens.len() {
+            Ok(value)
+        } else {
+            Err(parser.invalid())
+        }
+    });
+    Some(result)
+}
+
+/// A recursive descent parser for integer arithmetic
+struct Arithmetic<'a> {
+    text: &'a str,
+    tokens: &'a [&'a str],
+    pos: usize,
+}
+
+impl Arithmetic<'_> {
+    fn invalid(&self) -> String {
+        format!("Invalid expression `{}`", self.text.trim())
+    }
+
+    /// Get the current token if it's an operator
+    fn peek(&self) -> Option<char> {
+        self.tokens
+            .get(self.pos)
+            .and_then(|x| x.chars().next())
+            .filter(|x| !x.is_ascii_digit())
+    }
+
+    /// Apply an operator, checking for overflow and division by zero
+    fn apply(&self, op: char, a: i32, b: i32) -> Result<i32, String> {
+        if (op == '/' || op == '%') && b == 0 {
+            return Err(format!("Division by zero in `{}`", self.text.trim()));
+        }
+        let result = match op {
+            '+' => a.checked_add(b),
+            '-' => a.checked_sub(b),
+            '*' => a.checked_mul(b),
+            '/' => a.checked_div(b),
+            _ => a.checked_rem(b),
+        };
+        result.ok_or_else(|| format!("Integer overflow in `{}`", self.text.trim()))
+    }
+
+    fn expr(&mut self) -> Result<i32, String> {
+        let mut value = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.pos += 1;
+            let right = self.term()?;
+            value = self.apply(op, value, right)?;
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<i32, String> {
+        let mut value = self.factor()?;
+        while let Some(op @ ('*' | '/' | '%')) = self.peek() {
+            self.pos += 1;
+            let right = self.factor()?;
+            value = self.apply(op, value, right)?;
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<i32, String> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                let value = self.factor()?;
+                self.apply('-', 0, value)
+            }
+            Some('(') => {
+                self.pos += 1;
+                let value = self.expr()?;
+                if self.peek() != Some(')') {
+                    return Err(self.invalid());
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            None if self.pos < self.tokens.len() => {
+                let number = self.tokens[self.pos];
+                self.pos += 1;
+                number
+                    .parse()
+                    .map_err(|_| format!("Integer overflow in `{}`", self.text.trim()))
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+}
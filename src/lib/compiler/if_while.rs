@@ -103,8 +103,10 @@ impl Compiler {
                 command_arg!("matches"),
                 command_arg!(if result { "1" } else { "0" }),
                 command_arg!("run"),
-                command_arg!("call"),
-                Node::CommandArg(format!("{}if_{}_{}", subfolder, result, chars)),
+                Node::CallFunction {
+                    name: format!("{}if_{}_{}", subfolder, result, chars),
+                    args: vec![],
+                },
             ];
 
             Node::MinecraftCommand {
@@ -117,18 +119,14 @@ impl Compiler {
         let if_false_call = if_call_function(false);
 
         let if_true_function = Node::Function {
-            name: format!("{}if_true_{}", subfolder, chars),
+            name: format!("if_true_{}", chars),
             contents: if_block.clone(),
         };
 
-        let if_false_function = if let Some(else_contents) = else_block {
-            Some(Node::Function {
-                name: format!("{}if_false_{}", subfolder, chars),
-                contents: else_contents.clone(),
-            })
-        } else {
-            None
-        };
+        let if_false_function = else_block.as_ref().map(|else_contents| Node::Function {
+            name: format!("if_false_{}", chars),
+            contents: else_contents.clone(),
+        });
 
         if let Some(if_init) = if_init_function {
             ast.push(if_init)
@@ -158,12 +156,10 @@ impl Compiler {
             let mut vec = vec![command_arg!("if")];
             vec.append(&mut condition.clone());
             vec.push(command_arg!("run"));
-            // Forces a space to be added between the condition and function call
-            vec.push(command_arg!(""));
-            vec.push(Node::CallFunction(format!(
-                "{}condition_{}",
-                subfolder, chars
-            )));
+            vec.push(Node::CallFunction {
+                name: format!("{}condition_{}", subfolder, chars),
+                args: vec![],
+            });
             vec
         };
 
@@ -180,7 +176,10 @@ impl Compiler {
         let loop_condition_contents = {
             let mut vec = vec![];
             vec.append(&mut contents.clone());
-            vec.push(Node::CallFunction(format!("{}while_{}", subfolder, chars)));
+            vec.push(Node::CallFunction {
+                name: format!("{}while_{}", subfolder, chars),
+                args: vec![],
+            });
             vec
         };
 
@@ -191,13 +190,58 @@ impl Compiler {
         };
 
         // Call to while loop function
-        let call = Node::CallFunction(format!("{}while_{}", subfolder, chars));
+        let call = Node::CallFunction {
+            name: format!("{}while_{}", subfolder, chars),
+            args: vec![],
+        };
 
         ast.append(&mut vec![loop_main, loop_condition, call]);
 
         ast
     }
 
+    /// Convert the contents of a `run { ... }` block into a function and the
+    /// arguments used to call it
+    pub(crate) fn convert_run_block(contents: &[Node], subfolder: &str) -> Vec<Node> {
+        let chars = Compiler::random_chars();
+
+        let block_function = Node::Function {
+            name: format!("block_{}", chars),
+            contents: contents.to_vec(),
+        };
+
+        let block_call = Node::CallFunction {
+            name: format!("{}block_{}", subfolder, chars),
+            args: vec![],
+        };
+
+        vec![block_function, command_arg!("run"), block_call]
+    }
+
+    /// Convert the contents of an `after` block into a function and a
+    /// command to schedule it
+    pub(crate) fn convert_after(duration: &str, contents: &[Node], subfolder: &str) -> Vec<Node> {
+        let chars = Compiler::random_chars();
+
+        let after_function = Node::Function {
+            name: format!("after_{}", chars),
+            contents: contents.to_vec(),
+        };
+
+        let schedule = Node::MinecraftCommand {
+            name: "schedule".into(),
+            args: vec![
+                Node::CallFunction {
+                    name: format!("{}after_{}", subfolder, chars),
+                    args: vec![],
+                },
+                Node::CommandArg(duration.into()),
+            ],
+        };
+
+        vec![after_function, schedule]
+    }
+
     /// Return a random string of 4 lowercase alphanumeric characters
     pub(crate) fn random_chars() -> String {
         rand::thread_rng()
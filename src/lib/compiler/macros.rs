@@ -16,41 +16,814 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 use super::{
-    parse::{DatabindParser, ParseResult, Rule},
+    parse::{DatabindParser, ParseContext, ParseResult, Rule},
     Compiler,
 };
-use crate::ast::Node;
-use pest::Parser;
-use std::collections::HashMap;
+use crate::{ast::Node, suggest};
+use pest::{
+    error::{Error, ErrorVariant, LineColLocation},
+    Parser, Span,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+};
+
+/// A parameter in a macro definition
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroParam {
+    pub name: String,
+    /// The value used if no argument is passed (eg. `"1"` for `$b = "1"`)
+    pub default: Option<String>,
+    /// Whether the parameter takes every remaining argument (eg. `$rest...`)
+    pub variadic: bool,
+}
+
+/// An argument passed to a macro call
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroArg {
+    /// The name of the parameter for named arguments (eg. `a` for `a="x"`)
+    pub name: Option<String>,
+    pub value: String,
+    /// The argument as it was written, used if a named argument's
+    /// name doesn't match a parameter
+    pub raw: String,
+}
+
+/// A position in a source file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    /// The path to the file, if known
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}:{}", file, self.line, self.column),
+            None => write!(f, "line {}, column {}", self.line, self.column),
+        }
+    }
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Macro {
-    pub args: Vec<String>,
+    pub params: Vec<MacroParam>,
     pub contents: String,
+    /// Where the macro's contents start. `None` for macros defined by other macros
+    pub location: Option<Location>,
+}
+
+/// The default maximum number of nested macro expansions
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// The maximum number of "in expansion of" notes shown for an error
+const MAX_EXPANSION_NOTES: usize = 8;
+const OMITTED_NOTE: &str = "note: further expansions omitted";
+
+/// The maximum number of similarly-named macros suggested for an undefined macro
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Defined macros and the calls currently being expanded
+#[derive(Clone, Debug)]
+pub struct Macros {
+    /// Macro definitions. Imported macros are prefixed with their namespace (eg. `math::add`)
+    pub defs: HashMap<String, Macro>,
+    /// The maximum number of nested macro expansions before compilation fails
+    pub max_depth: usize,
+    /// The path to the file being compiled, used to show where macros are defined
+    /// and to find imported files
+    pub file: Option<String>,
+    /// The folder that imported files are looked for in if they aren't
+    /// found relative to the importing file (usually the project's root)
+    pub import_root: Option<PathBuf>,
+    /// Names brought into scope with `!use` (eg. `add` for `!use math::add`)
+    aliases: HashMap<String, String>,
+    /// Namespaces brought into scope with `!use ns::*`
+    glob_uses: Vec<String>,
+    /// Files that have already been imported
+    imported: HashSet<PathBuf>,
+    /// The names of the macros being expanded, outermost first
+    stack: Vec<String>,
+    /// The variables declared with `var` in any file, used to check
+    /// the variables in `return` statements
+    pub(crate) vars: HashSet<String>,
+}
+
+impl Macros {
+    pub fn new(max_depth: usize) -> Self {
+        Macros {
+            defs: HashMap::new(),
+            max_depth,
+            file: None,
+            import_root: None,
+            aliases: HashMap::new(),
+            glob_uses: Vec::new(),
+            imported: HashSet::new(),
+            stack: Vec::new(),
+            vars: HashSet::new(),
+        }
+    }
+
+    /// Start compiling a new file. Names from `!use` only apply to the file they're in
+    pub fn start_file(&mut self, file: Option<String>) {
+        self.file = file;
+        self.aliases.clear();
+        self.glob_uses.clear();
+    }
+
+    /// Mark a file as imported
+    ///
+    /// # Returns
+    ///
+    /// Whether the file wasn't already imported
+    pub(crate) fn add_import(&mut self, path: PathBuf) -> bool {
+        self.imported.insert(path)
+    }
+
+    /// Bring a macro (`ns::name`) or every macro in a namespace (`ns::*`) into scope
+    pub(crate) fn use_path(&mut self, path: &str) -> Result<(), String> {
+        if let Some(namespace) = path.strip_suffix("::*") {
+            let prefix = format!("{}::", namespace);
+            if !self.defs.keys().any(|x| x.starts_with(&prefix)) {
+                return Err(format!("No macros found in namespace `{}`", namespace));
+            }
+            if !self.glob_uses.iter().any(|x| x == namespace) {
+                self.glob_uses.push(namespace.into());
+            }
+        } else {
+            if !self.defs.contains_key(path) {
+                return Err(format!("No macro named `{}` to use", path));
+            }
+            let name = path.rsplit("::").next().unwrap();
+            self.aliases.insert(name.into(), path.into());
+        }
+
+        Ok(())
+    }
+
+    /// Get the full name of the macro that a call refers to
+    ///
+    /// Names are looked up in this order:
+    ///
+    /// 1. Full names (eg. `math::add`)
+    /// 2. Macros in the same namespace as the macro being expanded
+    /// 3. Names from `!use ns::name`
+    /// 4. Macros defined without a namespace
+    /// 5. Namespaces from `!use ns::*`
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        if name.contains("::") {
+            return self.defs.get(name).map(|_| name.into());
+        }
+
+        let current_namespace = self
+            .stack
+            .last()
+            .and_then(|x| x.rsplit_once("::"))
+            .map(|(namespace, _)| namespace);
+
+        current_namespace
+            .map(|namespace| format!("{}::{}", namespace, name))
+            .filter(|x| self.defs.contains_key(x))
+            .or_else(|| self.aliases.get(name).cloned())
+            .or_else(|| Some(name.to_string()).filter(|x| self.defs.contains_key(x)))
+            .or_else(|| {
+                self.glob_uses
+                    .iter()
+                    .map(|namespace| format!("{}::{}", namespace, name))
+                    .find(|x| self.defs.contains_key(x))
+            })
+    }
+
+    /// Get an error message for a call of a macro that doesn't exist,
+    /// with suggestions for what may have been meant
+    pub(crate) fn undefined_message(&self, name: &str) -> String {
+        let mut message = format!("No macro named ?{} is defined", name);
+
+        let similar = suggest::similar_names(name, self.defs.keys().map(String::as_str));
+        match similar.as_slice() {
+            [] => {}
+            [similar] => message.push_str(&format!("\nhelp: did you mean ?{}?", similar)),
+            similar => message.push_str(&format!(
+                "\nhelp: similarly named macros: {}",
+                similar
+                    .iter()
+                    .take(MAX_SUGGESTIONS)
+                    .map(|x| format!("?{}", x))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+
+        let suffix = format!("::{}", name);
+        let mut namespaced: Vec<&String> =
+            self.defs.keys().filter(|x| x.ends_with(&suffix)).collect();
+        namespaced.sort();
+        for full_name in namespaced.iter() {
+            message.push_str(&format!(
+                "\nhelp: use ?{} or add `!use {}` to call the imported macro",
+                full_name, full_name
+            ));
+        }
+
+        // Macros defined by other macros only exist once the other macro is called
+        let definition = format!("!def {}", name);
+        let mut defined_by: Vec<&String> = self
+            .defs
+            .iter()
+            .filter(|(_, x)| {
+                x.contents
+                    .match_indices(&definition)
+                    .any(|(i, _)| !x.contents[i + definition.len()..].starts_with(is_name_char))
+            })
+            .map(|(x, _)| x)
+            .collect();
+        defined_by.sort();
+        for outer in defined_by.iter() {
+            message.push_str(&format!(
+                "\nnote: ?{} is defined by ?{}, so it can only be used after ?{} is called",
+                name, outer, outer
+            ));
+        }
+
+        if similar.is_empty() && namespaced.is_empty() && defined_by.is_empty() {
+            message.push_str(
+                "\nnote: macros can be defined in any compiled file, \
+                or imported from other files with `!import`",
+            );
+        }
+
+        message
+    }
+
+    /// Whether a macro is currently being expanded
+    pub fn expanding(&self) -> bool {
+        !self.stack.is_empty()
+    }
+
+    /// Get the chain of macro calls being expanded (eg. `?a -> ?b -> ?a`).
+    /// Repeated calls of the same macro are shown once (eg. `?a (x3)`)
+    pub fn call_chain(&self, next: &str) -> String {
+        let mut chain: Vec<(&str, usize)> = vec![];
+        for name in self.stack.iter().map(String::as_str).chain([next]) {
+            match chain.last_mut() {
+                Some((last, count)) if *last == name => *count += 1,
+                _ => chain.push((name, 1)),
+            }
+        }
+
+        chain
+            .iter()
+            .map(|(name, count)| match count {
+                1 => format!("?{}", name),
+                _ => format!("?{} (x{})", name, count),
+            })
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+}
+
+impl Default for Macros {
+    fn default() -> Self {
+        Macros::new(DEFAULT_MAX_DEPTH)
+    }
 }
 
 impl Macro {
-    pub fn expand_to_string(&self, args: &Vec<String>) -> String {
-        let mut expanded = self.contents.clone();
+    /// Match the arguments of a call to the macro's parameters
+    ///
+    /// # Returns
+    ///
+    /// A map of parameter names to values, or an error message if the arguments
+    /// don't match the parameters
+    pub fn bind_args(&self, args: &[MacroArg]) -> Result<HashMap<String, String>, String> {
+        let mut bound: HashMap<String, String> = HashMap::new();
+        let mut positional: Vec<&str> = vec![];
+        let mut extra: Vec<&str> = vec![];
 
-        for i in 0..self.args.len() {
-            expanded = expanded.replace(&format!("${}", self.args[i]), &args[i]);
+        let variadic = self.params.iter().find(|x| x.variadic);
+        let fixed: Vec<&MacroParam> = self.params.iter().filter(|x| !x.variadic).collect();
+
+        for arg in args {
+            // Named arguments are only treated as named if the name is a parameter
+            let param = arg
+                .name
+                .as_ref()
+                .and_then(|name| fixed.iter().find(|x| &x.name == name));
+
+            if let Some(param) = param {
+                if bound.contains_key(&param.name) {
+                    return Err(format!(
+                        "Argument for ${} was passed more than once",
+                        param.name
+                    ));
+                }
+                bound.insert(param.name.clone(), arg.value.clone());
+            } else if !bound.is_empty() {
+                return Err("Positional arguments must come before named arguments".into());
+            } else if arg.name.is_some() {
+                positional.push(&arg.raw);
+            } else {
+                positional.push(&arg.value);
+            }
+        }
+
+        for value in positional {
+            // Give the argument to the next parameter without a value
+            if let Some(param) = fixed.iter().find(|x| !bound.contains_key(&x.name)) {
+                bound.insert(param.name.clone(), value.into());
+            } else {
+                extra.push(value);
+            }
+        }
+
+        if let Some(variadic) = variadic {
+            bound.insert(variadic.name.clone(), extra.join(", "));
+        } else if !extra.is_empty() {
+            return Err(format!(
+                "Too many arguments (expected at most {}, got {})",
+                fixed.len(),
+                args.len()
+            ));
+        }
+
+        for param in fixed {
+            if !bound.contains_key(&param.name) {
+                match &param.default {
+                    Some(default) => {
+                        bound.insert(param.name.clone(), default.clone());
+                    }
+                    None => return Err(format!("Missing argument for ${}", param.name)),
+                }
+            }
+        }
+
+        Ok(bound)
+    }
+
+    /// Evaluate the macro's `!if` and `!for` blocks and replace parameters
+    /// in its contents with their values
+    ///
+    /// # Returns
+    ///
+    /// The expanded text, or an error message if a block is invalid
+    pub fn expand_to_string(&self, args: &HashMap<String, String>) -> Result<String, String> {
+        Ok(self.expand_with_lines(args)?.0)
+    }
+
+    /// Expand the macro, also getting the line of the macro's contents
+    /// that each line of the expanded text came from
+    fn expand_with_lines(
+        &self,
+        args: &HashMap<String, String>,
+    ) -> Result<(String, Vec<usize>), String> {
+        let mut lines = self.contents.split_inclusive('\n').enumerate();
+        let (blocks, end) = parse_blocks(&mut lines)?;
+        if let Some(end) = end {
+            return Err(format!("Unexpected `{}`", end));
         }
 
-        expanded
+        let mut expanded = String::with_capacity(self.contents.len());
+        let mut source_lines: Vec<usize> = vec![];
+        render_blocks(&blocks, args, &mut expanded, &mut source_lines)?;
+        Ok((expanded, source_lines))
     }
 
-    pub fn expand_to_ast(
+    /// Move an error inside of an expansion of the macro to the call site,
+    /// adding a note with the error's location in the macro's definition
+    fn expansion_error(
         &self,
-        args: &Vec<String>,
-        macros: &mut HashMap<String, Self>,
+        name: &str,
+        error: &Error<Rule>,
+        source_lines: &[usize],
+        span: Span,
+    ) -> Box<Error<Rule>> {
+        let (row, col) = match error.line_col {
+            LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
+        };
+
+        let note = match &self.location {
+            Some(location) => {
+                let source_line = source_lines
+                    .get(row - 1)
+                    .or_else(|| source_lines.last())
+                    .copied()
+                    .unwrap_or(0);
+                // The first line of the contents doesn't start at the beginning of the line
+                let column = if source_line == 0 {
+                    location.column + col - 1
+                } else {
+                    col
+                };
+                let location = Location {
+                    line: location.line + source_line,
+                    column,
+                    ..location.clone()
+                };
+                format!("note: in expansion of ?{} at {}", name, location)
+            }
+            None => format!("note: in expansion of ?{}", name),
+        };
+
+        let mut message = error.variant.message().into_owned();
+        let notes = message
+            .lines()
+            .filter(|x| x.starts_with("note: in expansion of"))
+            .count();
+        if notes < MAX_EXPANSION_NOTES {
+            message.push('\n');
+            message.push_str(&note);
+        } else if !message.ends_with(OMITTED_NOTE) {
+            message.push('\n');
+            message.push_str(OMITTED_NOTE);
+        }
+
+        Box::new(Error::new_from_span(
+            ErrorVariant::CustomError { message },
+            span,
+        ))
+    }
+
+    /// Expand the macro and parse the expansion into an AST
+    pub(crate) fn expand_to_ast(
+        &self,
+        name: &str,
+        args: &HashMap<String, String>,
+        macros: &mut Macros,
         subfolder: &str,
+        context: ParseContext,
+        span: Span,
     ) -> ParseResult<Vec<Node>> {
-        let expanded = self.expand_to_string(args);
-        let tokens = DatabindParser::parse(Rule::file, &expanded)?
-            .next()
-            .unwrap();
-        let parsed = Compiler::parse_tokens(&mut tokens.into_inner(), macros, subfolder)?;
-        Ok(parsed)
+        self.expand_with(name, args, macros, span, |expanded, macros| {
+            let mut tokens = DatabindParser::parse(Rule::file, expanded)?;
+            let tokens = tokens.next().unwrap();
+            Compiler::parse_tokens(&mut tokens.into_inner(), macros, subfolder, context)
+        })
+    }
+
+    /// Expand the macro and any macros called by the expansion
+    /// without parsing anything else
+    pub fn expand_to_source(
+        &self,
+        name: &str,
+        args: &HashMap<String, String>,
+        macros: &mut Macros,
+        span: Span,
+    ) -> ParseResult<String> {
+        self.expand_with(name, args, macros, span, |expanded, macros| {
+            let tokens = DatabindParser::parse(Rule::file, expanded)?;
+            Compiler::expand_tokens(expanded, tokens, macros)
+        })
+    }
+
+    /// Expand the macro and pass the expansion to `f`, checking the depth limit
+    /// and pointing errors from `f` at the call site
+    fn expand_with<T>(
+        &self,
+        name: &str,
+        args: &HashMap<String, String>,
+        macros: &mut Macros,
+        span: Span,
+        f: impl FnOnce(&str, &mut Macros) -> ParseResult<T>,
+    ) -> ParseResult<T> {
+        if macros.stack.len() >= macros.max_depth {
+            let message = format!(
+                "Macro expansion depth limit of {} exceeded in call of ?{}\nnote: call chain: {}",
+                macros.max_depth,
+                name,
+                macros.call_chain(name),
+            );
+            return Err(Box::new(Error::new_from_span(
+                ErrorVariant::CustomError { message },
+                span,
+            )));
+        }
+
+        let (expanded, source_lines) = self.expand_with_lines(args).map_err(|message| {
+            Error::new_from_span(
+                ErrorVariant::CustomError {
+                    message: format!("{} in expansion of ?{}", message, name),
+                },
+                span,
+            )
+        })?;
+
+        macros.stack.push(name.into());
+        let result = f(&expanded, macros);
+        macros.stack.pop();
+        result.map_err(|error| self.expansion_error(name, &error, &source_lines, span))
+    }
+}
+
+/// A piece of a macro's contents
+#[derive(Clone, Debug, PartialEq)]
+enum Block {
+    /// A line of text, including its newline, and its line in the macro's contents
+    Text(usize, String),
+    /// `!if condition` ... `!else` ... `!endif`
+    If {
+        condition: String,
+        if_blocks: Vec<Block>,
+        else_blocks: Vec<Block>,
+    },
+    /// `!for $var in values` ... `!endfor`
+    For {
+        var: String,
+        values: String,
+        contents: Vec<Block>,
+    },
+}
+
+/// Split a macro's contents into blocks
+///
+/// # Returns
+///
+/// The blocks and the directive that ended them (`!else`, `!endif`, or `!endfor`),
+/// if any
+fn parse_blocks<'a, I: Iterator<Item = (usize, &'a str)>>(
+    lines: &mut I,
+) -> Result<(Vec<Block>, Option<&'a str>), String> {
+    let mut blocks: Vec<Block> = vec![];
+    // Nested macro definitions are evaluated when they're called, not here
+    let mut def_depth = 0;
+
+    while let Some((line_num, line)) = lines.next() {
+        let trimmed = line.trim();
+        let directive = trimmed.split_whitespace().next().unwrap_or("");
+
+        if def_depth > 0 || !["!if", "!else", "!endif", "!for", "!endfor"].contains(&directive) {
+            if directive == "!def" {
+                def_depth += 1;
+            } else if directive == "!end" && def_depth > 0 {
+                def_depth -= 1;
+            }
+            blocks.push(Block::Text(line_num, line.into()));
+            continue;
+        }
+
+        let rest = trimmed[directive.len()..].trim();
+        match directive {
+            "!if" => {
+                if rest.is_empty() {
+                    return Err("Missing condition for `!if`".into());
+                }
+                let (if_blocks, end) = parse_blocks(lines)?;
+                let else_blocks = match end {
+                    Some("!endif") => vec![],
+                    Some("!else") => match parse_blocks(lines)? {
+                        (else_blocks, Some("!endif")) => else_blocks,
+                        _ => return Err("Missing `!endif` for `!if`".into()),
+                    },
+                    _ => return Err("Missing `!endif` for `!if`".into()),
+                };
+                blocks.push(Block::If {
+                    condition: rest.into(),
+                    if_blocks,
+                    else_blocks,
+                });
+            }
+            "!for" => {
+                let (var, values) = match rest.split_once(" in ") {
+                    Some((var, values)) if var.trim().starts_with('$') => {
+                        (var.trim()[1..].to_string(), values.trim().to_string())
+                    }
+                    _ => return Err("Expected `!for $name in values`".into()),
+                };
+                let contents = match parse_blocks(lines)? {
+                    (contents, Some("!endfor")) => contents,
+                    _ => return Err("Missing `!endfor` for `!for`".into()),
+                };
+                blocks.push(Block::For {
+                    var,
+                    values,
+                    contents,
+                });
+            }
+            _ => return Ok((blocks, Some(directive))),
+        }
+    }
+
+    Ok((blocks, None))
+}
+
+/// Evaluate blocks and add the resulting text to `expanded`.
+/// The line in the macro's contents of each added line is added to `source_lines`
+fn render_blocks(
+    blocks: &[Block],
+    vars: &HashMap<String, String>,
+    expanded: &mut String,
+    source_lines: &mut Vec<usize>,
+) -> Result<(), String> {
+    for block in blocks {
+        match block {
+            Block::Text(line_num, text) => {
+                let text = substitute(text, vars);
+                // Values with newlines add more than one line
+                source_lines.extend(text.matches('\n').map(|_| *line_num));
+                if !text.ends_with('\n') {
+                    source_lines.push(*line_num);
+                }
+                expanded.push_str(&text);
+            }
+            Block::If {
+                condition,
+                if_blocks,
+                else_blocks,
+            } => {
+                if eval_condition(condition, vars)? {
+                    render_blocks(if_blocks, vars, expanded, source_lines)?;
+                } else {
+                    render_blocks(else_blocks, vars, expanded, source_lines)?;
+                }
+            }
+            Block::For {
+                var,
+                values,
+                contents,
+            } => {
+                let mut vars = vars.clone();
+                for value in eval_values(values, &vars)? {
+                    vars.insert(var.clone(), value);
+                    render_blocks(contents, &vars, expanded, source_lines)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Evaluate an `!if` condition such as `$x == "debug"`, `$x != 1`, or `$x`.
+/// A value on its own is true unless it's empty, `0`, or `false`
+fn eval_condition(condition: &str, vars: &HashMap<String, String>) -> Result<bool, String> {
+    let (left, op, right) = match find_operator(condition) {
+        Some((i, op)) => (&condition[..i], op, Some(&condition[i + op.len()..])),
+        None => (condition, "", None),
+    };
+
+    if left.trim().is_empty() || right.is_some_and(|x| x.trim().is_empty()) {
+        return Err(format!("Invalid condition `{}`", condition));
+    }
+
+    let left = unquote(&substitute(left.trim(), vars));
+    match right {
+        Some(right) => {
+            let right = unquote(&substitute(right.trim(), vars));
+            Ok((left == right) == (op == "=="))
+        }
+        None => Ok(!["", "0", "false"].contains(&left.as_str())),
     }
 }
+
+/// Find the first `==` or `!=` in a condition that isn't in quotes,
+/// so `$x != "a==b"` compares `$x` with `a==b`
+fn find_operator(condition: &str) -> Option<(usize, &'static str)> {
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in condition.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            _ => {
+                let rest = &condition[i..];
+                if let Some(op) = ["==", "!="].iter().copied().find(|x| rest.starts_with(x)) {
+                    return Some((i, op));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Evaluate the values of a `!for` loop, either a range such as `0..8` or `1..=8`,
+/// or a list such as `("red", "blue")`
+fn eval_values(values: &str, vars: &HashMap<String, String>) -> Result<Vec<String>, String> {
+    let values = substitute(values, vars);
+    let values = values.trim();
+
+    if let Some(list) = values.strip_prefix('(').and_then(|x| x.strip_suffix(')')) {
+        return Ok(split_list(list).iter().map(|x| unquote(x)).collect());
+    }
+
+    let (start, end, inclusive) = if let Some((start, end)) = values.split_once("..=") {
+        (start, end, true)
+    } else if let Some((start, end)) = values.split_once("..") {
+        (start, end, false)
+    } else {
+        return Err(format!(
+            "Expected a range or a list of values in `!for`, got `{}`",
+            values
+        ));
+    };
+
+    let parse_int = |x: &str| {
+        x.trim()
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid range `{}` in `!for`", values))
+    };
+    let (start, end) = (parse_int(start)?, parse_int(end)?);
+
+    if inclusive {
+        Ok((start..=end).map(|x| x.to_string()).collect())
+    } else {
+        Ok((start..end).map(|x| x.to_string()).collect())
+    }
+}
+
+/// Split a comma-separated list, ignoring commas inside of quotes
+fn split_list(list: &str) -> Vec<&str> {
+    let mut items: Vec<&str> = vec![];
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in list.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                items.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    let last = list[start..].trim();
+    if !last.is_empty() {
+        items.push(last);
+    }
+
+    items
+}
+
+/// Remove the quotes around a value if it has them
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        Some(inner) => inner.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.into(),
+    }
+}
+
+/// Replace parameters in text with their values
+///
+/// `$name` only matches a whole identifier, so `$a` doesn't touch `$abc`.
+/// `${name}` can be used when a parameter is followed by more text,
+/// and `$$` becomes a literal `$`. Substitution is done in a single pass,
+/// so values containing `$` are never replaced again. Anything that isn't
+/// in `vars` is left as-is
+fn substitute(text: &str, vars: &HashMap<String, String>) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+
+        // Get the identifier and the length of the text it was written as
+        let (name, len) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = rest
+                .char_indices()
+                .find(|(i, c)| {
+                    !(c.is_ascii_alphabetic() || *c == '_' || (*i > 0 && c.is_ascii_digit()))
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            (&rest[..end], end)
+        };
+
+        match vars.get(name) {
+            Some(value) if !name.is_empty() => {
+                expanded.push_str(value);
+                rest = &rest[len..];
+            }
+            _ => expanded.push('$'),
+        }
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Whether a character can be part of a macro name
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
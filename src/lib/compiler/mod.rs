@@ -16,9 +16,13 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 pub mod compile;
+pub mod consts;
 pub mod parse;
 
+mod cfg;
 pub(crate) mod if_while;
 pub mod macros;
+pub mod std_macros;
+mod vars;
 
 pub struct Compiler;
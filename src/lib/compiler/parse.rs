@@ -15,79 +15,510 @@
  * You should have received a copy of the GNU General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
-use super::{macros::Macro, Compiler};
+use super::{
+    macros::{Location, Macro, MacroArg, MacroParam, Macros},
+    Compiler,
+};
 use crate::{
-    ast::{AssignmentOp, Node},
+    ast::{AssignmentOp, Node, Value},
     compiler::if_while::{IfStatement, WhileLoop},
+    suggest::closest_match,
+};
+use pest::{
+    error::{Error, ErrorVariant, LineColLocation},
+    iterators::{Pair, Pairs},
+    Parser, Span,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
 };
-use pest::{iterators::Pairs, Parser};
-use std::collections::HashMap;
+
+/// Information about where the tokens being parsed are
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ParseContext<'a> {
+    /// The parameters of the function the tokens are in, if they're in one
+    pub(crate) function: Option<&'a [String]>,
+}
 
 #[derive(Parser)]
 #[grammar = "databind.pest"]
 /// Pest's parser
 pub(crate) struct DatabindParser;
 
-pub type ParseResult<T> = Result<T, pest::error::Error<Rule>>;
+pub type ParseResult<T> = Result<T, Box<pest::error::Error<Rule>>>;
+
+macro_rules! percent_escape {
+    ($str: expr) => {
+        if let Some(stripped) = $str.strip_prefix("%") {
+            stripped.into()
+        } else {
+            $str.into()
+        }
+    };
+}
+
+macro_rules! unwrap_name {
+    ($inner: expr) => {{
+        // Get the name as str
+        let as_str = $inner.next().unwrap().as_str();
+        // Remove % prefix if present
+        percent_escape!(as_str)
+    }};
+}
+
+macro_rules! custom_error {
+    ($span: expr, $message: expr) => {
+        Box::new(Error::new_from_span(
+            ErrorVariant::CustomError { message: $message },
+            $span,
+        ))
+    };
+}
+
+macro_rules! parse_value {
+    ($value: expr) => {{
+        let value = $value.into_inner().next().unwrap();
+        match value.as_rule() {
+            Rule::integer => Value::Integer(value.as_str().parse().unwrap()),
+            _ => Value::Var(percent_escape!(value.as_str())),
+        }
+    }};
+}
+
+macro_rules! fix_escapes {
+    ($str: expr) => {
+        $str.replace("\\\\", "\\")
+            .replace("\\/", "/")
+            .replace("\\\"", "\"")
+            .replace("\\n", "\n")
+            .replace("\\r", "\r")
+            .replace("\\t", "\t")
+    };
+}
+
+/// Get the value of a quoted or bare macro argument
+macro_rules! macro_arg {
+    ($arg: expr) => {{
+        let arg = $arg;
+        match arg.as_rule() {
+            Rule::string => fix_escapes!(arg.into_inner().as_str()),
+            _ => arg.as_str().trim().to_string(),
+        }
+    }};
+}
 
 impl Compiler {
     /// Convert the provided file contents into an AST
-    pub fn parse(
-        raw_file: &str,
-        subfolder: &str,
-        macros: &mut HashMap<String, Macro>,
-    ) -> ParseResult<Vec<Node>> {
-        let tokens = DatabindParser::parse(Rule::file, &raw_file)?
-            .next()
-            .unwrap();
-        Compiler::parse_tokens(&mut tokens.into_inner(), macros, subfolder)
+    pub fn parse(raw_file: &str, subfolder: &str, macros: &mut Macros) -> ParseResult<Vec<Node>> {
+        let mut tokens = DatabindParser::parse(Rule::file, raw_file)?;
+        // Macros in the file can be used before they're defined
+        Compiler::collect_macro_defs(tokens.clone(), macros, None)?;
+        let tokens = tokens.next().unwrap();
+        Compiler::parse_tokens(
+            &mut tokens.into_inner(),
+            macros,
+            subfolder,
+            ParseContext::default(),
+        )
     }
 
-    /// Convert the provided tokens into an AST
-    pub(crate) fn parse_tokens(
-        tokens: &mut Pairs<Rule>,
-        macros: &mut HashMap<String, Macro>,
-        subfolder: &str,
-    ) -> ParseResult<Vec<Node>> {
-        let mut ast = vec![];
+    /// Add the macros defined or imported and the variables declared in the provided
+    /// file contents without compiling anything else. Used to allow macros to be
+    /// defined in any order
+    pub fn collect_macros(raw_file: &str, macros: &mut Macros) -> ParseResult<()> {
+        let tokens = DatabindParser::parse(Rule::file, raw_file)?;
+        Compiler::collect_macro_defs(tokens, macros, None)
+    }
+
+    /// Add the macros defined or imported and the variables declared in the provided
+    /// tokens, prefixing macro names with a namespace if one is given
+    fn collect_macro_defs(
+        tokens: Pairs<Rule>,
+        macros: &mut Macros,
+        namespace: Option<&str>,
+    ) -> ParseResult<()> {
+        for token in tokens.flatten() {
+            match token.as_rule() {
+                Rule::macro_def => {
+                    let (name, macro_def) = Compiler::parse_macro_def(token, macros)?;
+                    let name = match namespace {
+                        Some(namespace) => format!("{}::{}", namespace, name),
+                        None => name,
+                    };
+                    macros.defs.insert(name, macro_def);
+                }
+                Rule::import => Compiler::import_macros(token, macros)?,
+                Rule::new_var => {
+                    let name: String = unwrap_name!(token.into_inner());
+                    macros.vars.insert(name);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add the macros defined or imported in the contents of a file to a namespace
+    pub(crate) fn collect_namespaced_macros(
+        raw_file: &str,
+        file: &str,
+        namespace: &str,
+        macros: &mut Macros,
+    ) -> ParseResult<()> {
+        // Imports and macro locations in the file are relative to it
+        let previous_file = macros.file.replace(file.into());
+        let result = DatabindParser::parse(Rule::file, raw_file)
+            .map_err(Box::new)
+            .and_then(|tokens| Compiler::collect_macro_defs(tokens, macros, Some(namespace)));
+        macros.file = previous_file;
+        result
+    }
 
-        macro_rules! percent_escape {
-            ($str: expr) => {
-                if let Some(stripped) = $str.strip_prefix("%") {
-                    stripped.into()
+    /// Get the full name, definition, and arguments of the macro called by a `macro_call` token
+    fn bind_macro_call(
+        token: Pair<Rule>,
+        macros: &Macros,
+    ) -> ParseResult<(String, Macro, HashMap<String, String>)> {
+        let span = token.as_span();
+        let mut inner = token.into_inner();
+        let name: String = unwrap_name!(inner);
+        let args: Vec<MacroArg> = inner
+            .map(|x| {
+                let raw = x.as_str().trim().to_string();
+                if let Rule::named_arg = x.as_rule() {
+                    let mut named = x.into_inner();
+                    let name = unwrap_name!(named);
+                    let value = macro_arg!(named.next().unwrap());
+                    MacroArg {
+                        name: Some(name),
+                        value,
+                        raw,
+                    }
                 } else {
-                    $str.into()
+                    MacroArg {
+                        name: None,
+                        value: macro_arg!(x),
+                        raw,
+                    }
+                }
+            })
+            .collect();
+
+        let name = macros
+            .resolve(&name)
+            .ok_or_else(|| custom_error!(span, macros.undefined_message(&name)))?;
+        let macro_def = macros.defs[&name].clone();
+
+        let args = macro_def
+            .bind_args(&args)
+            .map_err(|message| custom_error!(span, format!("{} in call of ?{}", message, name)))?;
+
+        Ok((name, macro_def, args))
+    }
+
+    /// Expand the macros in the provided file contents without compiling anything else.
+    /// Macro definitions, `!import`, and `!use` are removed and `!!` is replaced with
+    /// the text after it. Everything else is left as-is
+    pub fn expand(raw_file: &str, macros: &mut Macros) -> ParseResult<String> {
+        let tokens = DatabindParser::parse(Rule::file, raw_file)?;
+        // Macros in the file can be used before they're defined
+        Compiler::collect_macro_defs(tokens.clone(), macros, None)?;
+        let expanded = Compiler::expand_tokens(raw_file, tokens, macros)?;
+        // Don't leave the blank lines between removed definitions at the start
+        Ok(expanded.trim_start_matches(&['\r', '\n'][..]).to_string())
+    }
+
+    /// Expand the macros in the provided tokens, parsed from `raw`
+    pub(crate) fn expand_tokens(
+        raw: &str,
+        tokens: Pairs<Rule>,
+        macros: &mut Macros,
+    ) -> ParseResult<String> {
+        let mut expanded = String::with_capacity(raw.len());
+        // The end of the last replaced token
+        let mut last = 0;
+
+        for token in tokens.flatten() {
+            let span = token.as_span();
+            // Skip the tokens inside of replaced tokens
+            if span.start() < last {
+                continue;
+            }
+
+            let replacement = match token.as_rule() {
+                Rule::macro_def => {
+                    let (name, macro_def) = Compiler::parse_macro_def(token, macros)?;
+                    macros.defs.insert(name, macro_def);
+                    String::new()
                 }
+                Rule::import => {
+                    Compiler::import_macros(token, macros)?;
+                    String::new()
+                }
+                Rule::use_macro => {
+                    let path: String = unwrap_name!(token.into_inner());
+                    macros
+                        .use_path(&path)
+                        .map_err(|message| custom_error!(span, message))?;
+                    String::new()
+                }
+                Rule::trustme => {
+                    let content: String = unwrap_name!(token.into_inner());
+                    content.trim_start().to_string()
+                }
+                Rule::macro_call => {
+                    let (name, macro_def, args) = Compiler::bind_macro_call(token, macros)?;
+                    let text = macro_def.expand_to_source(&name, &args, macros, span)?;
+                    let line_start = raw[..span.start()].rfind('\n').map_or(0, |x| x + 1);
+                    let indent: String = raw[line_start..]
+                        .chars()
+                        .take_while(|x| *x == ' ' || *x == '\t')
+                        .collect();
+                    reindent(&text, &indent)
+                }
+                _ => continue,
             };
+
+            let (mut start, mut end) = (span.start(), span.end());
+            // Remove lines that only had a removed token on them
+            if replacement.is_empty() {
+                let line_start = raw[..start].rfind('\n').map_or(0, |x| x + 1);
+                let line_end = raw[end..].find('\n').map_or(raw.len(), |x| end + x + 1);
+                if raw[line_start..start].trim().is_empty() && raw[end..line_end].trim().is_empty()
+                {
+                    start = line_start.max(last);
+                    end = line_end;
+                }
+            }
+
+            expanded.push_str(&raw[last..start]);
+            expanded.push_str(&replacement);
+            last = end;
         }
 
-        macro_rules! unwrap_name {
-            ($inner: expr) => {{
-                // Get the name as str
-                let as_str = $inner.next().unwrap().as_str();
-                // Remove % prefix if present
-                percent_escape!(as_str)
-            }};
+        expanded.push_str(&raw[last..]);
+        Ok(expanded)
+    }
+
+    /// Get the name and definition of a macro from a `macro_def` token
+    fn parse_macro_def(token: Pair<Rule>, macros: &Macros) -> ParseResult<(String, Macro)> {
+        let mut inner = token.into_inner();
+        let name: String = unwrap_name!(inner);
+        let mut params: Vec<MacroParam> = vec![];
+        // The variadic parameter and where it's defined, if there is one
+        let mut variadic_param: Option<(String, Span)> = None;
+
+        for param in inner.next().unwrap().into_inner() {
+            let span = param.as_span();
+            let mut param = param.into_inner();
+            let param_name: String = unwrap_name!(param);
+            let (default, variadic) = match param.next() {
+                Some(x) if x.as_rule() == Rule::variadic => (None, true),
+                Some(x) => (Some(macro_arg!(x)), false),
+                None => (None, false),
+            };
+
+            // Arguments are bound to a variadic parameter after the others,
+            // so it has to be the only one and come last
+            if let Some((variadic_name, variadic_span)) = &variadic_param {
+                return Err(if variadic {
+                    custom_error!(
+                        span,
+                        format!(
+                            "Macro ?{} has more than one variadic parameter\n\
+                             help: remove the `...` from ${} or ${}",
+                            name, variadic_name, param_name
+                        )
+                    )
+                } else {
+                    custom_error!(
+                        *variadic_span,
+                        format!(
+                            "Variadic parameter ${} of ?{} must be the last parameter\n\
+                             help: move ${}... after ${}",
+                            variadic_name, name, variadic_name, param_name
+                        )
+                    )
+                });
+            }
+            if variadic {
+                variadic_param = Some((param_name.clone(), span));
+            }
+
+            params.push(MacroParam {
+                name: param_name,
+                default,
+                variadic,
+            });
+        }
+
+        let contents = inner.next().unwrap();
+        // Positions in macro expansions don't match the file
+        let location = if macros.expanding() {
+            None
+        } else {
+            let (line, column) = contents.as_span().start_pos().line_col();
+            Some(Location {
+                file: macros.file.clone(),
+                line,
+                column,
+            })
+        };
+        let contents: String = percent_escape!(contents.as_str());
+
+        Ok((
+            name,
+            Macro {
+                params,
+                contents,
+                location,
+            },
+        ))
+    }
+
+    /// Add the macros from a file imported with `!import "path"`.
+    /// The macros are put in a namespace named after the file (eg. `math` for `lib/math.databind`).
+    /// Paths are relative to the file being compiled, or to `macros.import_root`
+    /// if the file doesn't exist there
+    fn import_macros(token: Pair<Rule>, macros: &mut Macros) -> ParseResult<()> {
+        let span = token.as_span();
+        let import_path = token.into_inner().next().unwrap().into_inner().as_str();
+        let import_path = fix_escapes!(import_path);
+
+        let base = macros
+            .file
+            .as_ref()
+            .and_then(|x| Path::new(x).parent())
+            .map_or_else(PathBuf::new, Path::to_path_buf);
+        let path = match (base.join(&import_path), &macros.import_root) {
+            (path, Some(root)) if !path.exists() => root.join(&import_path),
+            (path, _) => path,
+        };
+        let path = path.canonicalize().map_err(|e| {
+            custom_error!(span, format!("Failed to import `{}`: {}", import_path, e))
+        })?;
+
+        let namespace = path.file_stem().unwrap().to_string_lossy().to_string();
+        let valid_namespace = namespace.starts_with(|x: char| x.is_ascii_alphabetic())
+            && namespace
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || x == '_');
+        if !valid_namespace {
+            return Err(custom_error!(
+                span,
+                format!(
+                    "Imported file `{}` does not have a valid namespace name",
+                    import_path
+                )
+            ));
+        }
+
+        if !macros.add_import(path.clone()) {
+            return Ok(());
         }
 
-        macro_rules! fix_escapes {
-            ($str: expr) => {
-                $str.replace("\\\\", "\\")
-                    .replace("\\/", "/")
-                    .replace("\\\"", "\"")
-                    .replace("\\n", "\n")
-                    .replace("\\r", "\r")
-                    .replace("\\t", "\t")
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            custom_error!(span, format!("Failed to import `{}`: {}", import_path, e))
+        })?;
+
+        let file = path.display().to_string();
+        Compiler::collect_namespaced_macros(&contents, &file, &namespace, macros).map_err(|error| {
+            let (row, col) = match error.line_col {
+                LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
             };
+            let message = format!(
+                "{}\nnote: in imported file {}:{}:{}",
+                error.variant.message(),
+                path.display(),
+                row,
+                col
+            );
+            custom_error!(span, message)
+        })
+    }
+
+    /// Resolve the path of a called function relative to the current subfolder
+    ///
+    /// - `ns:path` is left as-is
+    /// - `/path` is relative to the namespace's `functions` folder
+    /// - `path`, `./path`, and `../path` are relative to the current subfolder
+    ///
+    /// # Returns
+    ///
+    /// The path without a namespace (eg. `cmd/func`), or the original path if
+    /// it had a namespace
+    pub(crate) fn resolve_path(path: &str, subfolder: &str) -> Result<String, String> {
+        if path.contains(':') {
+            return Ok(path.into());
+        }
+
+        let (mut segments, relative) = if let Some(absolute) = path.strip_prefix('/') {
+            (vec![], absolute)
+        } else {
+            (
+                subfolder.split('/').filter(|x| !x.is_empty()).collect(),
+                path,
+            )
+        };
+
+        for segment in relative.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    if segments.pop().is_none() {
+                        return Err(format!(
+                            "Function path `{}` goes outside of the functions folder",
+                            path
+                        ));
+                    }
+                }
+                _ => segments.push(segment),
+            }
+        }
+
+        if segments.is_empty() {
+            return Err(format!("Function path `{}` does not name a function", path));
+        }
+
+        Ok(segments.join("/"))
+    }
+
+    /// Get an error message for a `return` of a variable that isn't declared anywhere,
+    /// with a suggestion for what may have been meant
+    fn undefined_var_message(name: &str, params: &[String], macros: &Macros) -> String {
+        let mut message = format!("No variable named `{}` is defined", name);
+        let known = params.iter().chain(macros.vars.iter()).map(String::as_str);
+        match closest_match(name, known) {
+            Some(similar) => message.push_str(&format!("\nhelp: did you mean `{}`?", similar)),
+            None => message.push_str(
+                "\nhelp: declare it with `var`, or use `return run` or `return fail` \
+                 for Minecraft's `return` command",
+            ),
         }
+        message
+    }
+
+    /// Convert the provided tokens into an AST
+    pub(crate) fn parse_tokens(
+        tokens: &mut Pairs<Rule>,
+        macros: &mut Macros,
+        subfolder: &str,
+        context: ParseContext,
+    ) -> ParseResult<Vec<Node>> {
+        let mut ast = vec![];
 
         for token in tokens {
             match token.as_rule() {
                 /* Variables and objectives */
                 Rule::new_var => {
                     let mut inner = token.into_inner();
-                    let name = unwrap_name!(inner);
+                    let name: String = unwrap_name!(inner);
                     let value: i32 = inner.next().unwrap().as_str().parse().unwrap();
+                    // Variables can also be declared by macros
+                    macros.vars.insert(name.clone());
                     ast.push(Node::NewVar { name, value });
                 }
                 Rule::set_var => {
@@ -164,11 +595,30 @@ impl Compiler {
                         } else {
                             vec![]
                         };
-                        args.append(&mut Compiler::parse_tokens(&mut inner, macros, subfolder)?);
+                        args.append(&mut Compiler::parse_tokens(
+                            &mut inner, macros, subfolder, context,
+                        )?);
                         args
                     };
+
+                    // Functions generated for `run { ... }` blocks can't be
+                    // part of the command, so they're moved in front of it
+                    let (mut functions, args): (Vec<Node>, Vec<Node>) = args
+                        .into_iter()
+                        .partition(|x| matches!(x, Node::Function { .. }));
+
+                    ast.append(&mut functions);
                     ast.push(Node::MinecraftCommand { name, args });
                 }
+                Rule::run_block => {
+                    let contents = Compiler::parse_tokens(
+                        &mut token.into_inner(),
+                        macros,
+                        subfolder,
+                        context,
+                    )?;
+                    ast.append(&mut Compiler::convert_run_block(&contents, subfolder));
+                }
                 Rule::command_arg => {
                     let as_str = token.as_str();
                     ast.push(Node::CommandArg(if as_str == "%=" {
@@ -179,8 +629,29 @@ impl Compiler {
                 }
                 Rule::function => {
                     let mut inner = token.into_inner();
-                    let name = unwrap_name!(inner);
-                    let contents = Compiler::parse_tokens(&mut inner, macros, subfolder)?;
+                    let name: String = unwrap_name!(inner);
+                    let params: Vec<String> =
+                        if let Some(Rule::func_params) = inner.peek().map(|x| x.as_rule()) {
+                            inner
+                                .next()
+                                .unwrap()
+                                .into_inner()
+                                .map(|x| percent_escape!(x.as_str()))
+                                .collect()
+                        } else {
+                            vec![]
+                        };
+                    let function_context = ParseContext {
+                        function: Some(&params),
+                    };
+                    let contents =
+                        Compiler::parse_tokens(&mut inner, macros, subfolder, function_context)?;
+                    let contents = Compiler::lower_function_body(
+                        &contents,
+                        &format!("{}{}", subfolder, name),
+                        &params,
+                        true,
+                    );
                     ast.push(Node::Function { name, contents });
                 }
                 Rule::tag => {
@@ -188,8 +659,46 @@ impl Compiler {
                     ast.push(Node::Tag(unwrap_name!(inner)));
                 }
                 Rule::call_function => {
+                    let span = token.as_span();
                     let mut inner = token.into_inner();
-                    ast.push(Node::CallFunction(unwrap_name!(inner)));
+                    let name: String = unwrap_name!(inner);
+                    let name = Compiler::resolve_path(&name, subfolder)
+                        .map_err(|message| custom_error!(span, message))?;
+                    let args = if let Some(args) = inner.next() {
+                        args.into_inner().map(|x| parse_value!(x)).collect()
+                    } else {
+                        vec![]
+                    };
+                    ast.push(Node::CallFunction { name, args });
+                }
+                Rule::return_statement => {
+                    let span = token.as_span();
+                    let params = context.function.ok_or_else(|| {
+                        custom_error!(
+                            span,
+                            "Found `return` outside of a function\nhelp: move it into a `func` block"
+                                .into()
+                        )
+                    })?;
+                    let mut inner = token.into_inner();
+                    let value = parse_value!(inner.next().unwrap());
+                    if let Value::Var(name) = &value {
+                        if !params.contains(name) && !macros.vars.contains(name) {
+                            return Err(custom_error!(
+                                span,
+                                Compiler::undefined_var_message(name, params, macros)
+                            ));
+                        }
+                    }
+                    ast.push(Node::Return(value));
+                }
+                Rule::return_value => {
+                    let span = token.as_span();
+                    let mut inner = token.into_inner();
+                    let name: String = unwrap_name!(inner);
+                    let name = Compiler::resolve_path(&name, subfolder)
+                        .map_err(|message| custom_error!(span, message))?;
+                    ast.push(Node::ReturnValue(name));
                 }
                 /* Statements/loops */
                 Rule::if_statement => {
@@ -198,17 +707,20 @@ impl Compiler {
                         &mut inner.next().unwrap().into_inner(),
                         macros,
                         subfolder,
+                        context,
                     )?;
                     let if_block = Compiler::parse_tokens(
                         &mut inner.next().unwrap().into_inner(),
                         macros,
                         subfolder,
+                        context,
                     )?;
                     let else_block = if let Some(tokens) = inner.next() {
                         Some(Compiler::parse_tokens(
                             &mut tokens.into_inner(),
                             macros,
                             subfolder,
+                            context,
                         )?)
                     } else {
                         None
@@ -228,11 +740,13 @@ impl Compiler {
                         &mut inner.next().unwrap().into_inner(),
                         macros,
                         subfolder,
+                        context,
                     )?;
                     let contents = Compiler::parse_tokens(
                         &mut inner.next().unwrap().into_inner(),
                         macros,
                         subfolder,
+                        context,
                     )?;
 
                     let while_loop = WhileLoop {
@@ -242,38 +756,41 @@ impl Compiler {
 
                     ast.append(&mut Compiler::convert_while(&while_loop, subfolder));
                 }
-                Rule::macro_def => {
+                Rule::after_block => {
                     let mut inner = token.into_inner();
-                    let name = unwrap_name!(inner);
-                    let args = inner
-                        .next()
-                        .unwrap()
-                        .into_inner()
-                        .map(|x| x.as_str().into())
-                        .collect();
-                    let contents = unwrap_name!(inner);
+                    let duration = inner.next().unwrap().as_str();
+                    let contents = Compiler::parse_tokens(
+                        &mut inner.next().unwrap().into_inner(),
+                        macros,
+                        subfolder,
+                        context,
+                    )?;
 
+                    ast.append(&mut Compiler::convert_after(duration, &contents, subfolder));
+                }
+                Rule::macro_def => {
+                    let (name, macro_def) = Compiler::parse_macro_def(token, macros)?;
                     // Add def to list of macros
-                    macros.insert(name, Macro { args, contents });
+                    macros.defs.insert(name, macro_def);
+                }
+                Rule::import => Compiler::import_macros(token, macros)?,
+                Rule::use_macro => {
+                    let span = token.as_span();
+                    let path: String = unwrap_name!(token.into_inner());
+                    macros
+                        .use_path(&path)
+                        .map_err(|message| custom_error!(span, message))?;
                 }
                 Rule::macro_call => {
-                    let mut inner = token.into_inner();
-                    let name: String = unwrap_name!(inner);
-                    let args: Vec<String> = inner
-                        .map(|x| fix_escapes!(x.into_inner().as_str()).into())
-                        .collect();
-
-                    let macro_def = macros
-                        .get(&name)
-                        .clone()
-                        .expect(&format!("No macro definition found for call of {}", name))
-                        .clone();
+                    let span = token.as_span();
+                    let (name, macro_def, args) = Compiler::bind_macro_call(token, macros)?;
 
                     // Expand macro call
                     // This ends up happening recursively since parse_tokens is recalled for every new nested call
                     // Also, we don't have to worry about adding definitions since we pass the reference to
                     // the HashMap of macro definitions!
-                    let mut expanded = macro_def.expand_to_ast(&args, macros, subfolder)?;
+                    let mut expanded =
+                        macro_def.expand_to_ast(&name, &args, macros, subfolder, context, span)?;
                     ast.append(&mut expanded);
                 }
                 Rule::trustme => {
@@ -289,3 +806,33 @@ impl Compiler {
         Ok(ast)
     }
 }
+
+/// Remove the indentation shared by every line of a macro expansion and
+/// indent every line after the first with `indent`
+fn reindent(text: &str, indent: &str) -> String {
+    let text = text.trim();
+    // The first line has its indentation removed already
+    let shared = text
+        .lines()
+        .skip(1)
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| x.len() - x.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 {
+                return line.to_string();
+            }
+            let line = line.get(shared..).unwrap_or_else(|| line.trim_start());
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,71 @@
+/*
+ * Databind - Expand the functionality of Minecraft Datapacks.
+ * Copyright (C) 2021  Adam Thompson-Sharpe
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! The standard macro library included with Databind
+use super::{macros::Macros, Compiler};
+use crate::suggest;
+use pest::error::LineColLocation;
+
+/// The modules of the standard macro library and their source code
+pub const MODULES: &[(&str, &str)] = &[
+    ("random", include_str!("std/random.databind")),
+    ("raycast", include_str!("std/raycast.databind")),
+    ("scores", include_str!("std/scores.databind")),
+    ("selectors", include_str!("std/selectors.databind")),
+    ("text", include_str!("std/text.databind")),
+    ("timer", include_str!("std/timer.databind")),
+];
+
+impl Macros {
+    /// Load modules of the standard macro library.
+    /// The macros of each module are put in the `std::<module>` namespace
+    /// (eg. `std::random::range`)
+    pub fn load_std(&mut self, modules: &[String]) -> Result<(), String> {
+        for module in modules {
+            let source = MODULES
+                .iter()
+                .find(|(name, _)| name == module)
+                .map(|(_, source)| source)
+                .ok_or_else(|| {
+                    let mut message = format!("Unknown standard macro module `{}`", module);
+                    let names = MODULES.iter().map(|(name, _)| *name);
+                    if let Some(similar) = suggest::closest_match(module, names) {
+                        message.push_str(&format!("\nhelp: did you mean `{}`?", similar));
+                    }
+                    message
+                })?;
+
+            let file = format!("<std>/{}.databind", module);
+            let namespace = format!("std::{}", module);
+            Compiler::collect_namespaced_macros(source, &file, &namespace, self).map_err(|e| {
+                let (row, col) = match e.line_col {
+                    LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
+                };
+                format!(
+                    "Failed to load standard macro module `{}`: {} at {}:{}:{}",
+                    module,
+                    e.variant.message(),
+                    file,
+                    row,
+                    col
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+}
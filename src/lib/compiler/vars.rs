@@ -0,0 +1,181 @@
+/*
+ * Databind - Expand the functionality of Minecraft Datapacks.
+ * Copyright (C) 2021  Adam Thompson-Sharpe
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Replaces global variables from `vars.toml` in source files
+use super::{parse::ParseResult, Compiler};
+use crate::suggest;
+use pest::{
+    error::{Error, ErrorVariant},
+    Position,
+};
+use std::collections::HashMap;
+
+impl Compiler {
+    /// Replace the global variables in a file (eg. `&name`, `&colors[0]`,
+    /// or `&team.red.color`) with their values. The keys of `vars` start with `&`
+    ///
+    /// Variables are matched as whole names, so `&name` doesn't touch `&names`.
+    /// `%&name` is left as the text `&name`, and comment lines are left alone.
+    /// Undefined names are errors unless they're in quotes (eg. `"Q&A"`).
+    /// Values are never replaced again, so they can safely contain `&`
+    pub fn substitute_vars(raw: &str, vars: &HashMap<String, String>) -> ParseResult<String> {
+        substitute(raw, vars, true)
+    }
+
+    /// Replace the global variables in text that isn't source code, such as the
+    /// header setting. Works like `substitute_vars`, but also replaces them in comments
+    pub fn substitute_text_vars(text: &str, vars: &HashMap<String, String>) -> ParseResult<String> {
+        substitute(text, vars, false)
+    }
+}
+
+/// Replace global variables in text, optionally skipping comment lines
+fn substitute(
+    raw: &str,
+    vars: &HashMap<String, String>,
+    skip_comments: bool,
+) -> ParseResult<String> {
+    let mut replaced = String::with_capacity(raw.len());
+    let mut offset = 0;
+
+    for line in raw.split_inclusive('\n') {
+        if skip_comments && line.trim_start().starts_with('#') {
+            replaced.push_str(line);
+            offset += line.len();
+            continue;
+        }
+
+        // The end of the last replaced variable in the line
+        let mut last = 0;
+        // Only `"` starts a string, since `'` is usually an apostrophe (eg. `say it's`)
+        let mut in_quotes = false;
+        let mut escaped = false;
+
+        for (i, c) in line.char_indices() {
+            if i < last {
+                continue;
+            }
+
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if in_quotes => escaped = true,
+                '"' => in_quotes = !in_quotes,
+                _ => {}
+            }
+            if c != '&' {
+                continue;
+            }
+
+            let ends = var_path_ends(&line[i + 1..]);
+            let longest = match ends.last() {
+                Some(end) => i + 1 + end,
+                None => continue,
+            };
+
+            if line[..i].ends_with('%') {
+                replaced.push_str(&line[last..i - 1]);
+                replaced.push_str(&line[i..longest]);
+                last = longest;
+                continue;
+            }
+
+            // Use the longest defined path, so `&name.` uses `&name`
+            let found = ends
+                .iter()
+                .rev()
+                .map(|end| &line[i..i + 1 + end])
+                .find_map(|name| vars.get(name).map(|value| (name, value)));
+
+            match found {
+                Some((name, value)) => {
+                    replaced.push_str(&line[last..i]);
+                    replaced.push_str(value);
+                    last = i + name.len();
+                }
+                // Text in strings (eg. chat messages or NBT) can have an `&` in it
+                None if in_quotes => {}
+                None => {
+                    let position = Position::new(raw, offset + i).unwrap();
+                    let message = undefined_message(&line[i..longest], vars);
+                    return Err(Box::new(Error::new_from_pos(
+                        ErrorVariant::CustomError { message },
+                        position,
+                    )));
+                }
+            }
+        }
+
+        replaced.push_str(&line[last..]);
+        offset += line.len();
+    }
+
+    Ok(replaced)
+}
+
+/// Get the length of the name at the start of some text
+pub(crate) fn name_len(text: &str) -> usize {
+    match text.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => text
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(text.len()),
+        _ => 0,
+    }
+}
+
+/// Get where each part of a variable path at the start of some text ends,
+/// eg. after `team`, `team.red`, and `team.red.color` for `team.red.color`
+fn var_path_ends(text: &str) -> Vec<usize> {
+    let mut ends = vec![];
+    let mut end = name_len(text);
+    if end == 0 {
+        return ends;
+    }
+    ends.push(end);
+
+    loop {
+        let rest = &text[end..];
+        if let Some(after) = rest.strip_prefix('.') {
+            let len = name_len(after);
+            if len == 0 {
+                break;
+            }
+            end += len + 1;
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let digits = after.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+            if digits == 0 || !after[digits..].starts_with(']') {
+                break;
+            }
+            end += digits + 2;
+        } else {
+            break;
+        }
+        ends.push(end);
+    }
+
+    ends
+}
+
+/// Get the error message for a variable that isn't defined
+fn undefined_message(name: &str, vars: &HashMap<String, String>) -> String {
+    let mut message = format!("No global variable named {} is defined", name);
+    match suggest::closest_match(name, vars.keys().map(|x| x.as_str())) {
+        Some(similar) => message.push_str(&format!("\nhelp: did you mean {}?", similar)),
+        None => message
+            .push_str("\nnote: global variables are defined in vars.toml. Use %& for a literal &"),
+    }
+    message
+}
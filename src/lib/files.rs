@@ -21,60 +21,103 @@ use glob::glob;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    fs,
-    path::{Path, PathBuf},
+    env, fs,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
 };
 use toml::Value;
 use walkdir::WalkDir;
 
+/// Check whether a folder name is used for functions
+/// (`functions`, or `function` since Minecraft 1.21)
+fn is_function_folder(name: &str) -> bool {
+    name == "functions" || name == "function"
+}
+
+/// Get the folders in a path
+fn folders(path: &Path) -> Vec<&str> {
+    path.components()
+        .filter_map(|x| match x {
+            Component::Normal(x) => x.to_str(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Find the functions folder of a namespace in a list of folders,
+/// preferring one in `data/<namespace>`
+fn function_folder_index(folders: &[&str]) -> Option<usize> {
+    (2..folders.len())
+        .rev()
+        .find(|&i| is_function_folder(folders[i]) && folders[i - 2] == "data")
+        .or_else(|| folders.iter().position(|x| is_function_folder(x)))
+}
+
 /// Get the prefix of a subfolder before a function call (eg. `"cmd/"` for
 /// a subfolder called `cmd`)
 /// Used for Databind calls, so a line like `call func` in a subfolder `cmd`
 /// will become `function namespace:cmd/func`
 pub fn get_subfolder_prefix<P: AsRef<Path>>(functions_path: &P) -> String {
-    let mut after_functions = PathBuf::from(
-        functions_path
-            .as_ref()
-            .to_str()
-            .unwrap()
-            .split("functions")
-            .last()
-            .unwrap(),
-    );
-
-    after_functions.pop();
-
-    // Ensure no backslashes and remove leading slash, if present
-    let prefix = after_functions.to_str().unwrap().replace('\\', "/");
+    let folders = folders(functions_path.as_ref());
+    let subfolders = match function_folder_index(&folders) {
+        // The last part of the path is the file
+        Some(i) if i + 1 < folders.len() => &folders[i + 1..folders.len() - 1],
+        _ => &[],
+    };
 
-    if prefix == "/" {
+    if subfolders.is_empty() {
         String::new()
-    } else if let Some(new) = prefix.strip_prefix('/') {
-        format!("{}/", new)
     } else {
-        format!("{}/", prefix)
+        format!("{}/", subfolders.join("/"))
     }
 }
 
 /// Get namespace (name of folder containing the main /functions)
 pub fn get_namespace<P: AsRef<Path>>(functions_path: &P) -> Result<&str, &str> {
-    let namespace_folder = functions_path
-        .as_ref()
-        .to_str()
-        .unwrap()
-        .split("functions")
-        .next()
-        .unwrap();
-
-    let namespace_folder =
-        if let Some(new) = namespace_folder.strip_suffix(|x: char| ['\\', '/'].contains(&x)) {
-            new
-        } else {
-            namespace_folder
-        };
+    let folders = folders(functions_path.as_ref());
+    match function_folder_index(&folders) {
+        Some(i) if i > 0 => Ok(folders[i - 1]),
+        _ => folders.last().copied().ok_or("Path has no folders"),
+    }
+}
+
+/// Get the full name of a function tag from the path to its JSON file
+/// (eg. `namespace:tag` for `data/namespace/tags/functions/tag.json`)
+///
+/// # Returns
+///
+/// The name of the tag, or `None` if the path is not a function tag
+pub fn get_tag_name<P: AsRef<Path>>(tag_path: &P) -> Option<String> {
+    let path = tag_path.as_ref().to_str()?.replace('\\', "/");
+    let (before, after) = path
+        .rsplit_once("/tags/functions/")
+        .or_else(|| path.rsplit_once("/tags/function/"))?;
+    let tag = after.strip_suffix(".json")?;
+    let namespace = before.rsplit('/').next()?;
 
-    let folders = namespace_folder.split(|x: char| ['\\', '/'].contains(&x));
-    Ok(folders.last().unwrap())
+    Some(format!("{}:{}", namespace, tag))
+}
+
+/// Rename the folders for functions and function tags in a path
+/// (eg. `data/namespace/functions`) to `folder`.
+/// Used to output the folder names for the target version of Minecraft
+pub fn rename_function_folders<P: AsRef<Path>>(path: P, folder: &str) -> PathBuf {
+    let folders = folders(path.as_ref());
+    let mut renamed = if path.as_ref().is_absolute() {
+        PathBuf::from("/")
+    } else {
+        PathBuf::new()
+    };
+
+    for (i, name) in folders.iter().enumerate() {
+        let in_namespace = |depth: usize| i >= depth && folders[i - depth] == "data";
+        let renames = is_function_folder(name)
+            && (in_namespace(2) || in_namespace(3) && folders[i - 1] == "tags");
+        renamed.push(if renames { folder } else { name });
+    }
+
+    renamed
 }
 
 /// Convert multiple globs into a `Vec<PathBuf>`
@@ -85,7 +128,7 @@ pub fn merge_globs(globs: &[String], prefix: &str) -> Vec<PathBuf> {
         let relative_files_glob = format!("{}/{}", prefix, files_glob);
 
         let mut files: Vec<PathBuf> = glob(&relative_files_glob)
-            .expect(&format!("Failed to parse glob {}", files_glob)[..])
+            .unwrap_or_else(|_| panic!("Failed to parse glob {}", files_glob))
             .filter_map(Result::ok)
             .collect();
         merged_globs.append(&mut files);
@@ -128,10 +171,13 @@ pub fn find_config_in_parents(
 /// - `target_folder` - The output directory for compiled files
 /// - `tag_map` - A map of tags to a vector of functions with that tag
 ///   (eg. `{"load": ["namespace:main"]}`)
+/// - `function_folder` - The name of the folder for function tags
+///   (`functions`, or `function` since Minecraft 1.21)
 pub fn create_tag_files<P: AsRef<Path>>(
     src_dir: P,
     target_folder: P,
     tag_map: &HashMap<String, Vec<String>>,
+    function_folder: &str,
 ) -> std::io::Result<()> {
     #[derive(Deserialize, Serialize)]
     struct TagFile {
@@ -140,25 +186,29 @@ pub fn create_tag_files<P: AsRef<Path>>(
 
     let target = target_folder.as_ref().display();
     // Create tags directory
-    fs::create_dir_all(format!("{}/data/minecraft/tags/functions", target))?;
+    fs::create_dir_all(format!(
+        "{}/data/minecraft/tags/{}",
+        target, function_folder
+    ))?;
 
     for (tag, funcs) in tag_map.iter() {
         let mut tag_file = TagFile {
             values: funcs.clone(),
         };
 
-        {
-            // Path to potential source JSON file
+        // Potential source JSON files, which can use either folder name
+        for folder in ["functions", "function"] {
             let path_str = format!(
-                "{}/data/minecraft/tags/functions/{}.json",
+                "{}/data/minecraft/tags/{}/{}.json",
                 src_dir.as_ref().display(),
+                folder,
                 tag
             );
             let path = Path::new(&path_str);
 
             // Read existing tags if present
             if path.exists() && path.is_file() {
-                let contents = fs::read_to_string(&path)?;
+                let contents = fs::read_to_string(path)?;
                 let mut existing_tags: TagFile = serde_json::from_str(&contents)?;
                 tag_file.values.append(&mut existing_tags.values);
             }
@@ -168,7 +218,10 @@ pub fn create_tag_files<P: AsRef<Path>>(
 
         // Write tag file
         fs::write(
-            &format!("{}/data/minecraft/tags/functions/{}.json", target, tag),
+            format!(
+                "{}/data/minecraft/tags/{}/{}.json",
+                target, function_folder, tag
+            ),
             json,
         )?;
     }
@@ -208,34 +261,191 @@ pub fn prioritize_macro_files<P: AsRef<Path>>(src_dir: P) -> Vec<PathBuf> {
 }
 
 /// Read the vars.toml file into a HashMap of Strings
-pub fn read_vars_toml<P: AsRef<Path>>(vars_toml: P) -> HashMap<String, String> {
-    let contents = fs::read_to_string(vars_toml).unwrap();
+///
+/// Arrays and tables are flattened, so `colors = ["red"]` becomes `&colors[0]`
+/// and `[team.red] color = "red"` becomes `&team.red.color`. Arrays of plain values
+/// are also available as a list (eg. `&colors` becomes `("red")`) to use in `!for`
+pub fn read_vars_toml<P: AsRef<Path>>(vars_toml: P) -> Result<HashMap<String, String>, String> {
+    let path = vars_toml.as_ref();
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
     // Read toml file into HashMap with multiple types
-    let vars_multi_type: HashMap<String, Value> = toml::from_str(&contents).unwrap();
+    let vars_multi_type: HashMap<String, Value> = toml::from_str(&contents)
+        .map_err(|e| format!("Invalid vars file {}: {}", path.display(), e))?;
+    let mut vars: HashMap<String, String> = HashMap::new();
+    override_vars(&mut vars, &vars_multi_type);
+    Ok(vars)
+}
+
+/// Override vars read with `read_vars_toml` (eg. with the vars of a profile).
+/// Arrays and tables replace the old value completely instead of being merged
+pub fn override_vars(vars: &mut HashMap<String, String>, overrides: &HashMap<String, Value>) {
+    for (k, v) in overrides.iter() {
+        let key = format!("&{}", k);
+        vars.retain(|name, _| {
+            name != &key
+                && !name.starts_with(&format!("{}.", key))
+                && !name.starts_with(&format!("{}[", key))
+        });
+        flatten_var(key, v, vars);
+    }
+}
+
+/// Get the global variables that are defined for every project.
+/// Variables in vars.toml with the same name replace these
+///
+/// - `&databind_version` - The version of Databind compiling the project
+/// - `&build_time` - When the project was compiled (eg. `2021-08-01T12:00:00Z`)
+/// - `&git_commit` - The short hash of the project's git commit, or `unknown`
+/// - `&pack_name` - The name of the project's folder
+/// - `&pack_format` - The `pack_format` from the project's pack.mcmeta, if there is one
+///
+/// # Arguments
+///
+/// - `datapack` - The root folder of the project
+pub fn builtin_vars<P: AsRef<Path>>(datapack: P) -> HashMap<String, String> {
+    let datapack = datapack.as_ref();
     let mut vars: HashMap<String, String> = HashMap::new();
-    for (k, v) in vars_multi_type.iter() {
-        // Try to convert the value into a string
-        let new_v: String = match v {
-            Value::String(value) => value.clone(),
-            Value::Boolean(value) => {
-                if *value {
-                    "1".into()
-                } else {
-                    "0".into()
-                }
+
+    vars.insert("&databind_version".into(), env!("CARGO_PKG_VERSION").into());
+    vars.insert("&build_time".into(), build_time());
+
+    let git_commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .current_dir(datapack)
+        .output()
+        .ok()
+        .filter(|x| x.status.success())
+        .and_then(|x| String::from_utf8(x.stdout).ok())
+        .map(|x| x.trim().to_string());
+    vars.insert(
+        "&git_commit".into(),
+        git_commit.unwrap_or_else(|| "unknown".into()),
+    );
+
+    if let Some(name) = datapack
+        .canonicalize()
+        .ok()
+        .and_then(|x| x.file_name().map(|x| x.to_string_lossy().into_owned()))
+    {
+        vars.insert("&pack_name".into(), name);
+    }
+
+    let src_dir = datapack.join("src");
+    let src_dir = if src_dir.is_dir() {
+        src_dir
+    } else {
+        datapack.into()
+    };
+    let pack_format = fs::read_to_string(src_dir.join("pack.mcmeta"))
+        .ok()
+        .and_then(|x| serde_json::from_str::<serde_json::Value>(&x).ok())
+        .and_then(|x| x["pack"]["pack_format"].as_i64());
+    if let Some(pack_format) = pack_format {
+        vars.insert("&pack_format".into(), pack_format.to_string());
+    }
+
+    vars
+}
+
+/// Get the global variables for a file, adding the ones that depend on
+/// the file (`&namespace`) to the project's variables
+pub fn file_vars(
+    vars: &HashMap<String, String>,
+    namespace: Option<&str>,
+) -> HashMap<String, String> {
+    let mut vars = vars.clone();
+    if let Some(namespace) = namespace {
+        vars.entry("&namespace".into())
+            .or_insert_with(|| namespace.into());
+    }
+    vars
+}
+
+/// Get the current time in UTC formatted as RFC 3339.
+/// Uses `SOURCE_DATE_EPOCH` instead if it's set to allow for reproducible builds
+fn build_time() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0)
+        });
+    let (days, time) = ((secs / 86400) as i64, secs % 86400);
+
+    // Convert days since 1970-01-01 into a date
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Add a value from vars.toml to `vars`, including every value inside of it
+/// if it's an array or table
+fn flatten_var(key: String, value: &Value, vars: &mut HashMap<String, String>) {
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                flatten_var(format!("{}[{}]", key, i), item, vars);
             }
-            Value::Float(value) => value.to_string(),
-            Value::Integer(value) => value.to_string(),
-            Value::Datetime(value) => value.to_string(),
-            _ => {
-                eprintln!(
-                    "error: Unsupported type found in vars.toml file (key: {}, value: {})",
-                    k, v
-                );
-                std::process::exit(1);
+
+            if items.iter().all(|x| !x.is_array() && !x.is_table()) {
+                let list: Vec<String> = items
+                    .iter()
+                    .map(|x| match x {
+                        Value::String(value) => {
+                            format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+                        }
+                        _ => var_to_string(x),
+                    })
+                    .collect();
+                vars.insert(key, format!("({})", list.join(", ")));
             }
-        };
-        vars.entry(format!("&{}", k)).or_insert(new_v);
+        }
+        Value::Table(table) => {
+            for (k, v) in table.iter() {
+                flatten_var(format!("{}.{}", key, k), v, vars);
+            }
+        }
+        _ => {
+            vars.insert(key, var_to_string(value));
+        }
+    }
+}
+
+/// Convert a plain value from vars.toml into a string
+fn var_to_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Boolean(value) => {
+            if *value {
+                "1".into()
+            } else {
+                "0".into()
+            }
+        }
+        Value::Float(value) => value.to_string(),
+        Value::Integer(value) => value.to_string(),
+        Value::Datetime(value) => value.to_string(),
+        Value::Array(_) | Value::Table(_) => value.to_string(),
     }
-    vars
 }
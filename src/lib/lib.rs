@@ -29,8 +29,7 @@
 //! for each file:
 //!
 //! ```rust
-//! use databind::compiler::{Compiler, macros::Macro};
-//! use std::collections::HashMap;
+//! use databind::compiler::{Compiler, macros::Macros};
 //!
 //! fn main() {
 //!     // Databind source file
@@ -44,7 +43,7 @@
 //!     .to_string();
 //!
 //!     // Keep track of global macros
-//!     let mut macros: HashMap<String, Macro> = HashMap::new();
+//!     let mut macros = Macros::default();
 //!
 //!     // Compiled
 //!     let compiled = Compiler::compile(&source_file, "", None, &mut macros)
@@ -80,7 +79,11 @@ extern crate pest_derive;
 pub mod ast;
 pub mod compiler;
 pub mod files;
+pub mod pack;
 mod settings;
+mod suggest;
+pub mod symbols;
+pub mod version;
 pub use settings::Settings;
 
-// Trigger CI
\ No newline at end of file
+// Trigger CI
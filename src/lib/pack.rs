@@ -0,0 +1,168 @@
+/*
+ * Databind - Expand the functionality of Minecraft Datapacks.
+ * Copyright (C) 2021  Adam Thompson-Sharpe
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Contains the `[pack]` table of databind.toml, which is used
+//! to generate the `pack.mcmeta` file
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+use toml::Value;
+
+/// Metadata for a datapack from the `[pack]` table of databind.toml
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pack {
+    /// The name of the pack, used for `&pack_name`.
+    /// Defaults to the name of the project's folder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The pack format for the version of Minecraft that the pack is for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pack_format: Option<u32>,
+    /// The description shown in the pack list. Can be a string or JSON text
+    pub description: Value,
+    /// The pack formats that the pack supports, either as one format,
+    /// a `[min, max]` range, or a table with `min_inclusive` and `max_inclusive`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supported_formats: Option<Value>,
+    /// Folders with files to use instead of the normal ones for some pack formats
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overlays: Vec<Overlay>,
+    /// Files from packs loaded before this one to hide
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub filter: Vec<FilterPattern>,
+}
+
+/// An overlay folder for some pack formats (`[[pack.overlays]]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Overlay {
+    /// The folder with the overlay's files, next to `data`
+    pub directory: String,
+    /// The pack formats to use the overlay for, in the same forms as `supported_formats`
+    pub formats: Value,
+}
+
+/// Files to hide from packs loaded before this one (`[[pack.filter]]`).
+/// Both are regular expressions, and a missing one matches everything
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FilterPattern {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Default for Pack {
+    fn default() -> Self {
+        Pack {
+            name: None,
+            pack_format: None,
+            description: Value::String(String::new()),
+            supported_formats: None,
+            overlays: Vec::new(),
+            filter: Vec::new(),
+        }
+    }
+}
+
+impl Pack {
+    /// Get the contents of the `pack.mcmeta` file for the pack
+    ///
+    /// # Returns
+    ///
+    /// The JSON for the file, or an error if a setting is invalid
+    pub fn to_mcmeta(&self) -> Result<String, String> {
+        let pack_format = self
+            .pack_format
+            .ok_or("The pack_format setting is required in [pack]\nhelp: set pack_format, or set minecraft_version to use its pack format")?;
+
+        let mut pack = Map::new();
+        pack.insert("pack_format".into(), pack_format.into());
+        pack.insert("description".into(), json_text(&self.description)?);
+        if let Some(formats) = &self.supported_formats {
+            pack.insert(
+                "supported_formats".into(),
+                format_range(formats, "supported_formats")?,
+            );
+        }
+
+        let mut mcmeta = Map::new();
+        mcmeta.insert("pack".into(), JsonValue::Object(pack));
+
+        if !self.overlays.is_empty() {
+            let mut entries = vec![];
+            for overlay in self.overlays.iter() {
+                let valid_chars =
+                    |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-".contains(c);
+                if overlay.directory.is_empty() || !overlay.directory.chars().all(valid_chars) {
+                    return Err(format!(
+                        "Invalid overlay directory `{}`\nnote: overlay directories can only use a-z, 0-9, _, and -",
+                        overlay.directory
+                    ));
+                }
+
+                entries.push(json!({
+                    "formats": format_range(&overlay.formats, "overlay formats")?,
+                    "directory": overlay.directory,
+                }));
+            }
+            mcmeta.insert("overlays".into(), json!({ "entries": entries }));
+        }
+
+        if !self.filter.is_empty() {
+            mcmeta.insert("filter".into(), json!({ "block": self.filter }));
+        }
+
+        Ok(serde_json::to_string_pretty(&mcmeta).unwrap())
+    }
+}
+
+/// Convert a description to JSON text. Strings are kept as strings,
+/// and tables and arrays are converted to JSON text components
+fn json_text(description: &Value) -> Result<JsonValue, String> {
+    match description {
+        Value::String(_) | Value::Table(_) | Value::Array(_) => {
+            serde_json::to_value(description).map_err(|e| e.to_string())
+        }
+        _ => Err("Invalid description in [pack]: expected a string or JSON text".into()),
+    }
+}
+
+/// Convert a pack format or range of them to JSON
+fn format_range(formats: &Value, setting: &str) -> Result<JsonValue, String> {
+    let is_format = |x: &Value| x.as_integer().is_some_and(|x| x >= 0);
+
+    let valid = match formats {
+        Value::Integer(_) => is_format(formats),
+        Value::Array(range) => range.len() == 2 && range.iter().all(is_format),
+        Value::Table(range) => {
+            range.len() == 2
+                && ["min_inclusive", "max_inclusive"]
+                    .iter()
+                    .all(|x| range.get(*x).is_some_and(is_format))
+        }
+        _ => false,
+    };
+
+    if valid {
+        serde_json::to_value(formats).map_err(|e| e.to_string())
+    } else {
+        Err(format!(
+            "Invalid {} in [pack]\nhelp: use a pack format (eg. 15), a range (eg. [15, 18]), or a table with min_inclusive and max_inclusive",
+            setting
+        ))
+    }
+}
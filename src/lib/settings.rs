@@ -17,26 +17,462 @@
  */
 //! Contains the Settings struct used to serialize/deserialize the databind.toml
 //! configuration file
+use crate::{
+    compiler::macros::DEFAULT_MAX_DEPTH,
+    pack::Pack,
+    suggest,
+    version::{MinecraftVersion, Target},
+};
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+use toml::{value::Table, Value};
+
+/// The current version of the databind.toml format
+pub const CONFIG_VERSION: i64 = 1;
+
+/// Changes to update configs from older versions of the format.
+/// The function at index `i` updates a config from version `i + 1` to `i + 2`
+const MIGRATIONS: &[fn(&mut Table)] = &[];
+
+/// The names of the settings that can be used in databind.toml
+const KEYS: &[&str] = &[
+    "version",
+    "inclusions",
+    "exclusions",
+    "output",
+    "max_macro_depth",
+    "std_macros",
+    "header",
+    "features",
+    "minecraft_version",
+    "namespace",
+    "subfolder",
+    "pack",
+    "vars",
+    "profile",
+];
+
+/// The settings that can be set with `-D key=value`. Other keys set global variables
+const DEFINE_KEYS: &[&str] = &[
+    "inclusions",
+    "exclusions",
+    "output",
+    "max_macro_depth",
+    "std_macros",
+    "header",
+    "features",
+    "minecraft_version",
+];
+
+/// The names of the settings that can be used in the `[pack]` table
+const PACK_KEYS: &[&str] = &[
+    "name",
+    "pack_format",
+    "description",
+    "supported_formats",
+    "overlays",
+    "filter",
+];
 
 /// Settings for the compiler
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Settings {
+    /// The version of the databind.toml format that the config uses
+    pub version: i64,
     /// A list of globs of files to include in compilation
     pub inclusions: Vec<String>,
     /// A list of globs of files to exclude in compilation
     pub exclusions: Vec<String>,
     /// The output directory for compiled files
     pub output: String,
+    /// The maximum number of nested macro expansions
+    pub max_macro_depth: usize,
+    /// Modules of the standard macro library to load (eg. `["random", "text"]`)
+    pub std_macros: Vec<String>,
+    /// Text to put at the start of every compiled function. Can use global variables
+    pub header: String,
+    /// Features to enable for conditional compilation (eg. `["debug"]`)
+    pub features: Vec<String>,
+    /// The version of Minecraft to compile for (eg. `"1.20.4"`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minecraft_version: Option<String>,
+    /// The namespace to put functions from a subdirectory in,
+    /// instead of the one from the folder structure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// The folder inside of `functions` to put functions from a subdirectory in,
+    /// instead of the one from the folder structure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subfolder: Option<String>,
+    /// Metadata used to generate the pack.mcmeta file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pack: Option<Pack>,
+    /// Values that override the ones in vars.toml
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, Value>,
+    /// Settings for build profiles (eg. `[profile.release]`) that override
+    /// the other settings when selected with `--profile`
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub profile: HashMap<String, Table>,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
+            version: CONFIG_VERSION,
             inclusions: vec!["**/*.databind".into()],
             exclusions: Vec::new(),
             output: "out".into(),
+            max_macro_depth: DEFAULT_MAX_DEPTH,
+            std_macros: Vec::new(),
+            header: String::new(),
+            features: Vec::new(),
+            minecraft_version: None,
+            namespace: None,
+            subfolder: None,
+            pack: None,
+            vars: HashMap::new(),
+            profile: HashMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Read settings from a config file
+    ///
+    /// # Returns
+    ///
+    /// The settings and warnings about the config (eg. unknown settings),
+    /// or an error if the config is invalid
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<(Settings, Vec<String>), String> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Settings::from_config(&contents, path)
+    }
+
+    /// Read settings from the contents of a config file. `path` is used in
+    /// errors and warnings
+    pub fn from_config(contents: &str, path: &Path) -> Result<(Settings, Vec<String>), String> {
+        let (config, warnings) = Settings::parse_config(contents, path)?;
+        let settings = Value::Table(config)
+            .try_into()
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        Ok((settings, warnings))
+    }
+
+    /// Parse a config file into a table, updating it from older versions of
+    /// the format and checking its settings. `path` is used in errors and warnings
+    ///
+    /// # Returns
+    ///
+    /// The config and warnings about it (eg. unknown settings),
+    /// or an error if the config is invalid
+    pub fn parse_config(contents: &str, path: &Path) -> Result<(Table, Vec<String>), String> {
+        let mut config: Table =
+            toml::from_str(contents).map_err(|e| config_error(e, contents, path))?;
+
+        let version = match config.get("version") {
+            None => CONFIG_VERSION,
+            Some(Value::Integer(version)) if (1..=CONFIG_VERSION).contains(version) => *version,
+            Some(Value::Integer(version)) if *version > CONFIG_VERSION => {
+                return Err(format!(
+                    "{} uses version {} of the config format, but this version of Databind only supports up to {}\nhelp: update Databind to use this config",
+                    path.display(),
+                    version,
+                    CONFIG_VERSION,
+                ))
+            }
+            Some(version) => {
+                return Err(format!(
+                    "Invalid config version {} in {}\nnote: the current version is {}",
+                    version,
+                    path.display(),
+                    CONFIG_VERSION,
+                ))
+            }
+        };
+
+        if version < CONFIG_VERSION {
+            for migration in &MIGRATIONS[version as usize - 1..] {
+                migration(&mut config);
+            }
+            config.insert("version".into(), Value::Integer(CONFIG_VERSION));
+        } else {
+            // Check the values of settings here to get where invalid ones are
+            toml::from_str::<Settings>(contents).map_err(|e| config_error(e, contents, path))?;
+        }
+
+        let mut warnings = unknown_keys(&config, KEYS, contents, path, None);
+        if let Some(Value::Table(profiles)) = config.get("profile") {
+            for (name, profile) in profiles {
+                if let Value::Table(profile) = profile {
+                    let table = format!("profile.{}", name);
+                    warnings.append(&mut unknown_keys(
+                        profile,
+                        KEYS,
+                        contents,
+                        path,
+                        Some(&table),
+                    ));
+                }
+            }
         }
+        if let Some(Value::Table(pack)) = config.get("pack") {
+            warnings.append(&mut unknown_keys(
+                pack,
+                PACK_KEYS,
+                contents,
+                path,
+                Some("pack"),
+            ));
+        }
+
+        Ok((config, warnings))
+    }
+
+    /// Get the version of Minecraft to compile for from the `minecraft_version`
+    /// setting and the pack format in `[pack]`
+    ///
+    /// # Returns
+    ///
+    /// The target, `None` if neither is set, or an error if the version is invalid
+    pub fn target(&self) -> Result<Option<Target>, String> {
+        let version = match &self.minecraft_version {
+            Some(version) => Some(version.parse::<MinecraftVersion>()?),
+            None => None,
+        };
+        let pack_format = self.pack.as_ref().and_then(|x| x.pack_format);
+
+        Ok(match (version, pack_format) {
+            (_, Some(pack_format)) => Some(Target {
+                version,
+                pack_format,
+            }),
+            (Some(version), None) => Some(Target {
+                version: Some(version),
+                pack_format: version.pack_format(),
+            }),
+            (None, None) => None,
+        })
     }
-}
\ No newline at end of file
+
+    /// Override settings with the ones from a profile.
+    /// The profile's `vars` are merged with the other vars instead of replacing them
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), String> {
+        let profile = self.profile.get(name).cloned().ok_or_else(|| {
+            let mut message = format!("No profile named {} is defined in databind.toml", name);
+            if let Some(similar) =
+                suggest::closest_match(name, self.profile.keys().map(|x| x.as_str()))
+            {
+                message.push_str(&format!("\nhelp: did you mean {}?", similar));
+            }
+            message
+        })?;
+
+        if profile.contains_key("profile") {
+            return Err(format!("Profile {} can't contain profiles", name));
+        }
+
+        self.merge(profile, &[])
+            .map_err(|e| format!("Invalid setting in profile {}: {}", name, e))
+    }
+
+    /// Check whether a `-D key=value` argument sets a setting instead of a global variable
+    pub fn is_define_setting(key: &str) -> bool {
+        DEFINE_KEYS.contains(&key)
+    }
+
+    /// Override settings with the ones from `-D key=value` arguments.
+    /// Keys that aren't settings are left alone, since they're global variables
+    pub fn apply_defines(&mut self, defines: &Table) -> Result<(), String> {
+        let overrides: Table = defines
+            .iter()
+            .filter(|(key, _)| Settings::is_define_setting(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        self.merge(overrides, &[])
+            .map_err(|e| format!("Invalid setting from -D: {}", e))
+    }
+
+    /// Override settings with the ones from a `databind.toml` in a subdirectory
+    /// of the project. `dir` is the subdirectory's path relative to the project,
+    /// which its `inclusions` and `exclusions` are relative to
+    ///
+    /// Its `features` and `exclusions` are added to the current ones, and its
+    /// profile named `profile` is applied if it has one. `namespace` and `subfolder`
+    /// are only kept if the subdirectory sets them
+    pub fn apply_dir_config(
+        &mut self,
+        mut config: Table,
+        dir: &str,
+        profile: Option<&str>,
+    ) -> Result<(), String> {
+        let config_name = format!("{}/databind.toml", dir);
+
+        let profiles = config.remove("profile");
+        if let (Some(Value::Table(mut profiles)), Some(profile)) = (profiles, profile) {
+            if let Some(Value::Table(profile)) = profiles.remove(profile) {
+                for (key, value) in profile {
+                    match (config.get_mut(&key), value) {
+                        (Some(Value::Table(vars)), Value::Table(value)) if key == "vars" => {
+                            vars.extend(value)
+                        }
+                        (_, value) => {
+                            config.insert(key, value);
+                        }
+                    }
+                }
+            }
+        }
+
+        for key in ["output", "std_macros", "pack", "minecraft_version"] {
+            if config.contains_key(key) {
+                return Err(format!(
+                    "The {} setting can't be changed in {}\nnote: it can only be set in the project's databind.toml",
+                    key, config_name,
+                ));
+            }
+        }
+
+        // Globs are relative to the subdirectory
+        for key in ["inclusions", "exclusions"] {
+            if let Some(Value::Array(globs)) = config.get_mut(key) {
+                for glob in globs.iter_mut() {
+                    if let Value::String(glob) = glob {
+                        *glob = format!("{}/{}", dir, glob);
+                    }
+                }
+            }
+        }
+
+        self.namespace = None;
+        self.subfolder = None;
+        self.merge(config, &["features", "exclusions"])
+            .map_err(|e| format!("Invalid setting in {}: {}", config_name, e))
+    }
+
+    /// Override settings with the ones in a table. `vars` are merged with the
+    /// current ones, and the arrays named in `appended` are added to the current ones
+    fn merge(&mut self, overrides: Table, appended: &[&str]) -> Result<(), toml::de::Error> {
+        let mut settings = match Value::try_from(&*self) {
+            Ok(Value::Table(settings)) => settings,
+            _ => unreachable!("Settings are always a table"),
+        };
+
+        for (key, value) in overrides {
+            match (settings.get_mut(&key), value) {
+                (Some(Value::Table(vars)), Value::Table(value)) if key == "vars" => {
+                    vars.extend(value)
+                }
+                (Some(Value::Array(current)), Value::Array(value))
+                    if appended.contains(&key.as_str()) =>
+                {
+                    current.extend(value)
+                }
+                (_, value) => {
+                    settings.insert(key, value);
+                }
+            }
+        }
+
+        *self = Value::Table(settings).try_into()?;
+        Ok(())
+    }
+}
+
+/// Get the error message for an invalid config
+fn config_error(error: toml::de::Error, contents: &str, path: &Path) -> String {
+    let message = error.to_string();
+    // The location is added after the message instead
+    let message = match message.rsplit_once(" at line ") {
+        Some((message, _)) => message,
+        None => &message,
+    };
+
+    match error.line_col() {
+        // The position is sometimes at the end of the file
+        Some((line, col)) if line < contents.lines().count() => format!(
+            "Invalid config: {} at {}:{}:{}",
+            message,
+            path.display(),
+            line + 1,
+            col + 1
+        ),
+        _ => format!("Invalid config {}: {}", path.display(), message),
+    }
+}
+
+/// Get warnings for the keys in a table that aren't in `keys`.
+/// `table_name` is the name of the table if it isn't the top-level one (eg. `pack`)
+fn unknown_keys(
+    table: &Table,
+    keys: &[&str],
+    contents: &str,
+    path: &Path,
+    table_name: Option<&str>,
+) -> Vec<String> {
+    let mut warnings = vec![];
+
+    for key in table.keys() {
+        if keys.contains(&key.as_str()) {
+            continue;
+        }
+
+        let mut warning = format!("Unknown setting `{}`", key);
+        if let Some(name) = table_name {
+            match name.strip_prefix("profile.") {
+                Some(profile) => warning.push_str(&format!(" in profile {}", profile)),
+                None => warning.push_str(&format!(" in [{}]", name)),
+            }
+        }
+        match key_line(contents, key, table_name) {
+            Some(line) => warning.push_str(&format!(" at {}:{}", path.display(), line)),
+            None => warning.push_str(&format!(" in {}", path.display())),
+        }
+        if let Some(similar) = suggest::closest_match(key, keys.iter().copied()) {
+            warning.push_str(&format!("\nhelp: did you mean `{}`?", similar));
+        }
+        warnings.push(warning);
+    }
+
+    warnings
+}
+
+/// Find the line that a key is set on, either as `key = ...` or `[key]`.
+/// `table_name` is the name of the table that the key is in, if any
+fn key_line(contents: &str, key: &str, table_name: Option<&str>) -> Option<usize> {
+    let table = table_name.map(String::from);
+    let full_key = match &table {
+        Some(table) => format!("{}.{}", table, key),
+        None => key.to_string(),
+    };
+    // Whether the current line is in the table that the key is in
+    let mut in_table = table.is_none();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim_start();
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .trim_start_matches('[')
+                .split(']')
+                .next()
+                .unwrap()
+                .trim();
+            if name == full_key || name.starts_with(&format!("{}.", full_key)) {
+                return Some(i + 1);
+            }
+            in_table = table.as_deref() == Some(name);
+        } else if in_table
+            && line
+                .strip_prefix(key)
+                .is_some_and(|x| x.trim_start().starts_with(['=', '.']))
+        {
+            return Some(i + 1);
+        }
+    }
+
+    None
+}
@@ -0,0 +1,66 @@
+/*
+ * Databind - Expand the functionality of Minecraft Datapacks.
+ * Copyright (C) 2021  Adam Thompson-Sharpe
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Contains functions used to suggest names for "did you mean" messages
+
+/// Get the number of single-character edits needed to turn one string into another
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let cost = if a_char == *b_char { 0 } else { 1 };
+            current.push(
+                (previous[j] + cost)
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Find the candidate most similar to a name, if any are similar enough
+pub(crate) fn closest_match<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    similar_names(name, candidates).into_iter().next()
+}
+
+/// Find the candidates similar enough to a name, most similar first
+pub(crate) fn similar_names<'a, I>(name: &str, candidates: I) -> Vec<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    // Allow roughly one typo for every three characters
+    let max_distance = (name.chars().count() / 3).max(1);
+
+    let mut similar: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .map(|x| (edit_distance(name, x), x))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    similar.sort_unstable();
+    similar.into_iter().map(|(_, x)| x).collect()
+}
@@ -0,0 +1,179 @@
+/*
+ * Databind - Expand the functionality of Minecraft Datapacks.
+ * Copyright (C) 2021  Adam Thompson-Sharpe
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Contains a table of the functions and function tags defined across a project,
+//! used to find references to ones that don't exist
+use crate::suggest::closest_match;
+use std::{
+    collections::HashSet,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// A reference to a function or tag that doesn't exist
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnresolvedReference {
+    /// The full name of the function or tag (eg. `namespace:func`)
+    pub name: String,
+    /// Whether the reference is to a function tag
+    pub is_tag: bool,
+    /// The source file containing the reference
+    pub source: PathBuf,
+    /// A similarly-named function or tag that does exist
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for UnresolvedReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, prefix) = if self.is_tag {
+            ("Function tag", "#")
+        } else {
+            ("Function", "")
+        };
+
+        write!(
+            f,
+            "{} {}{} referenced in {} does not exist",
+            kind,
+            prefix,
+            self.name,
+            self.source.display()
+        )?;
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\nhelp: did you mean {}{}?", prefix, suggestion)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Keeps track of every function and function tag defined in a project,
+/// as well as every reference to one
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    functions: HashSet<String>,
+    tags: HashSet<String>,
+    /// References as `(name, source file)`. Tag references start with `#`
+    references: Vec<(String, PathBuf)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    /// Add a defined function (eg. `namespace:cmd/func`)
+    pub fn add_function(&mut self, name: String) {
+        self.functions.insert(name);
+    }
+
+    /// Add a defined function tag (eg. `minecraft:load`)
+    pub fn add_tag(&mut self, name: String) {
+        self.tags.insert(name);
+    }
+
+    /// Add every function and tag referenced in the contents of an mcfunction file
+    ///
+    /// # Arguments
+    ///
+    /// - `contents` - The contents of the mcfunction file
+    /// - `source` - The source file that the contents came from
+    pub fn add_references<P: AsRef<Path>>(&mut self, contents: &str, source: P) {
+        for reference in find_references(contents) {
+            self.references
+                .push((reference, source.as_ref().to_path_buf()));
+        }
+    }
+
+    /// Get every reference to a function or tag that doesn't exist.
+    /// References to namespaces that the project doesn't define anything in
+    /// are ignored, since they may be from another datapack
+    pub fn unresolved(&self) -> Vec<UnresolvedReference> {
+        let namespaces = |names: &HashSet<String>| -> HashSet<String> {
+            names
+                .iter()
+                .map(|x| x.split(':').next().unwrap().to_string())
+                .collect()
+        };
+        let function_namespaces = namespaces(&self.functions);
+        let tag_namespaces = namespaces(&self.tags);
+
+        let mut unresolved = vec![];
+
+        for (reference, source) in self.references.iter() {
+            let (name, is_tag) = match reference.strip_prefix('#') {
+                Some(tag) => (tag, true),
+                None => (reference.as_str(), false),
+            };
+            let (defined, namespaces) = if is_tag {
+                (&self.tags, &tag_namespaces)
+            } else {
+                (&self.functions, &function_namespaces)
+            };
+
+            let namespace = name.split(':').next().unwrap();
+            if !namespaces.contains(namespace) || defined.contains(name) {
+                continue;
+            }
+
+            unresolved.push(UnresolvedReference {
+                name: name.into(),
+                is_tag,
+                source: source.clone(),
+                suggestion: closest_match(name, defined.iter().map(|x| x.as_str()))
+                    .map(|x| x.into()),
+            });
+        }
+
+        unresolved
+    }
+}
+
+/// Find every function and tag referenced by `function` commands in the
+/// contents of an mcfunction file. Tag references start with `#`
+///
+/// Only `function` at the start of a command, after `run`, or after `schedule`
+/// is treated as a reference, so text like `say call a function` is ignored
+pub fn find_references(contents: &str) -> Vec<String> {
+    let mut references = vec![];
+
+    for line in contents.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        for (i, word) in words.iter().enumerate() {
+            if *word != "function" || i + 1 >= words.len() {
+                continue;
+            }
+
+            if i == 0 || words[i - 1] == "run" || words[i - 1] == "schedule" {
+                let target = words[i + 1];
+                // Function names without a namespace are in the minecraft namespace
+                let target = if target.contains(':') {
+                    target.to_string()
+                } else if let Some(tag) = target.strip_prefix('#') {
+                    format!("#minecraft:{}", tag)
+                } else {
+                    format!("minecraft:{}", target)
+                };
+                references.push(target);
+            }
+        }
+    }
+
+    references
+}
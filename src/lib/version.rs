@@ -0,0 +1,262 @@
+/*
+ * Databind - Expand the functionality of Minecraft Datapacks.
+ * Copyright (C) 2021  Adam Thompson-Sharpe
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Contains the versions of Minecraft that packs can be compiled for
+//! and the features that each of them supports
+use std::{fmt, str::FromStr};
+
+/// The pack format of each range of Minecraft versions,
+/// as `(minor, first patch, last patch, pack format)`
+const PACK_FORMATS: &[(u32, u32, u32, u32)] = &[
+    (13, 0, 2, 4),
+    (14, 0, 4, 4),
+    (15, 0, 2, 5),
+    (16, 0, 1, 5),
+    (16, 2, 5, 6),
+    (17, 0, 1, 7),
+    (18, 0, 1, 8),
+    (18, 2, 2, 9),
+    (19, 0, 3, 10),
+    (19, 4, 4, 12),
+    (20, 0, 1, 15),
+    (20, 2, 2, 18),
+    (20, 3, 4, 26),
+    (20, 5, 6, 41),
+    (21, 0, 1, 48),
+    (21, 2, 3, 57),
+    (21, 4, 4, 61),
+    (21, 5, 5, 71),
+];
+
+/// Commands that were added or removed in a version of Minecraft,
+/// as `(command, minor, patch, added)`
+const COMMANDS: &[(&str, u32, u32, bool)] = &[
+    ("loot", 14, 0, true),
+    ("schedule", 14, 0, true),
+    ("teammsg", 14, 0, true),
+    ("tm", 14, 0, true),
+    ("spectate", 15, 0, true),
+    ("attribute", 16, 0, true),
+    ("locatebiome", 16, 0, true),
+    ("item", 17, 0, true),
+    ("replaceitem", 17, 0, false),
+    ("place", 19, 0, true),
+    ("locatebiome", 19, 0, false),
+    ("fillbiome", 19, 3, true),
+    ("damage", 19, 4, true),
+    ("ride", 19, 4, true),
+    ("return", 20, 0, true),
+    ("random", 20, 2, true),
+    ("tick", 20, 3, true),
+    ("transfer", 20, 5, true),
+    ("rotate", 21, 2, true),
+    ("test", 21, 5, true),
+];
+
+/// The first pack format to use `function` folders instead of `functions`
+const SINGULAR_FOLDERS_FORMAT: u32 = 45;
+/// The first pack format with the `return` command
+const RETURN_FORMAT: u32 = 15;
+/// The first pack format with `return run` and function macros
+const RETURN_RUN_FORMAT: u32 = 18;
+
+/// A release of Minecraft: Java Edition (eg. `1.20.4`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MinecraftVersion {
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl MinecraftVersion {
+    /// Get the data pack format for the version
+    pub fn pack_format(&self) -> u32 {
+        PACK_FORMATS
+            .iter()
+            .find(|(minor, first, last, _)| {
+                *minor == self.minor && (*first..=*last).contains(&self.patch)
+            })
+            .map(|(.., pack_format)| *pack_format)
+            .expect("versions are checked when parsed")
+    }
+}
+
+impl FromStr for MinecraftVersion {
+    type Err = String;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let parts: Option<Vec<u32>> = version.split('.').map(|x| x.parse().ok()).collect();
+        let parsed = match parts.as_deref() {
+            Some([1, minor]) => MinecraftVersion {
+                minor: *minor,
+                patch: 0,
+            },
+            Some([1, minor, patch]) => MinecraftVersion {
+                minor: *minor,
+                patch: *patch,
+            },
+            _ => {
+                return Err(format!(
+                    "Invalid Minecraft version `{}`\nhelp: use a release version like 1.20.4",
+                    version
+                ))
+            }
+        };
+
+        let known = PACK_FORMATS.iter().any(|(minor, first, last, _)| {
+            *minor == parsed.minor && (*first..=*last).contains(&parsed.patch)
+        });
+        if !known {
+            let (first, last) = (PACK_FORMATS[0], PACK_FORMATS[PACK_FORMATS.len() - 1]);
+            return Err(format!(
+                "Unknown Minecraft version {}\nnote: the known versions are 1.{}.{} to 1.{}.{}. Set pack_format in [pack] for other versions",
+                version, first.0, first.1, last.0, last.2
+            ));
+        }
+
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for MinecraftVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.patch == 0 {
+            write!(f, "1.{}", self.minor)
+        } else {
+            write!(f, "1.{}.{}", self.minor, self.patch)
+        }
+    }
+}
+
+/// The version of Minecraft that a pack is compiled for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    /// The version from the `minecraft_version` setting, if it's set
+    pub version: Option<MinecraftVersion>,
+    /// The pack format from `[pack]`, or the one for `version`
+    pub pack_format: u32,
+}
+
+impl Target {
+    /// Get the name of the folders that functions are in
+    /// (`function` since 1.21, and `functions` before it)
+    pub fn function_folder(&self) -> &'static str {
+        if self.pack_format >= SINGULAR_FOLDERS_FORMAT {
+            "function"
+        } else {
+            "functions"
+        }
+    }
+
+    /// Check whether the `return` command can be used
+    pub fn supports_return(&self) -> bool {
+        self.pack_format >= RETURN_FORMAT
+    }
+
+    /// Check whether `return run` and function macros can be used
+    pub fn supports_return_run(&self) -> bool {
+        self.pack_format >= RETURN_RUN_FORMAT
+    }
+
+    /// Get warnings for the commands in a compiled function that aren't
+    /// available in the target version
+    ///
+    /// # Arguments
+    ///
+    /// - `contents` - The contents of the compiled function
+    /// - `function` - The name of the function, used in warnings (eg. `namespace:main`)
+    pub fn check_commands(&self, contents: &str, function: &str) -> Vec<String> {
+        let mut warnings = vec![];
+        let target = match self.version {
+            Some(version) => format!("Minecraft {}", version),
+            None => format!("pack format {}", self.pack_format),
+        };
+
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let line = match line.strip_prefix('$') {
+                Some(line) if !self.supports_return_run() => {
+                    warnings.push(format!(
+                        "Function macro lines aren't available in {}, but are used in {}\nnote: they were added in 1.20.2",
+                        target, function
+                    ));
+                    line
+                }
+                Some(line) => line,
+                None => line,
+            };
+
+            // Commands are at the start of a line or after `run`
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let commands = words
+                .iter()
+                .take(1)
+                .chain(words.windows(2).filter(|x| x[0] == "run").map(|x| &x[1]));
+
+            for command in commands {
+                if let Some(warning) = self.check_command(command, &target, function) {
+                    if !warnings.contains(&warning) {
+                        warnings.push(warning);
+                    }
+                }
+            }
+        }
+
+        warnings
+    }
+
+    /// Get a warning for a command if it isn't available in the target version
+    fn check_command(&self, command: &str, target: &str, function: &str) -> Option<String> {
+        // Use the latest change to the command before or at the target version
+        let mut available = true;
+        let mut change = None;
+        for (name, minor, patch, added) in COMMANDS.iter().filter(|x| x.0 == command) {
+            let version = MinecraftVersion {
+                minor: *minor,
+                patch: *patch,
+            };
+            let changed = match self.version {
+                Some(target) => target >= version,
+                None => self.pack_format >= version.pack_format(),
+            };
+
+            if changed {
+                available = *added;
+                change = Some((name, version, added));
+            } else if *added {
+                // Not added yet
+                available = false;
+                change = Some((name, version, added));
+                break;
+            }
+        }
+
+        match change {
+            Some((name, version, added)) if !available => Some(format!(
+                "The `{}` command isn't available in {}, but is used in {}\nnote: it was {} in {}",
+                name,
+                target,
+                function,
+                if *added { "added" } else { "removed" },
+                version,
+            )),
+            _ => None,
+        }
+    }
+}
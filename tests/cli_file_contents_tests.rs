@@ -16,10 +16,12 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 use glob::glob;
+use regex::Regex;
 use serde::Deserialize;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::str;
+use tempdir::TempDir;
 
 mod tests;
 
@@ -63,7 +65,7 @@ fn test_tag_syntax() {
     tests::check_files_exist(&path, &expected_tags, "test_tag_syntax");
     // Check tag file contents
     for i in 0..expected_tags.len() {
-        path.push(&expected_tags[i]);
+        path.push(expected_tags[i]);
         let contents = fs::read_to_string(&path).unwrap();
         let contents_tag: TagFile = serde_json::from_str(&contents).unwrap();
         let expected_tag = TagFile {
@@ -181,17 +183,148 @@ fn test_while_creation() {
     );
 
     // Test the contents of the main while function
-    let while_contents = fs::read_to_string(&while_file).unwrap();
+    let while_contents = fs::read_to_string(while_file).unwrap();
     assert!(while_contents.contains(&format!(
         "execute if CONDITION run function {}",
         condition_func
     )));
     // Test the contents of the condition function
-    let condition_contents = fs::read_to_string(&condition_file).unwrap();
+    let condition_contents = fs::read_to_string(condition_file).unwrap();
     assert!(condition_contents.contains("say Inside loop"));
     assert!(condition_contents.contains(&format!("function {}", while_func)));
 }
 
+/// Test that `run { ... }` blocks are moved into their own functions
+#[test]
+fn test_run_block() {
+    let out = tests::run_in_tempdir("test_run_block").0;
+    let functions_path = format!("{}/data/test/functions", out.path().display());
+
+    let main_contents = fs::read_to_string(format!("{}/main.mcfunction", functions_path)).unwrap();
+    let blocks: Vec<PathBuf> = glob(&format!("{}/block_*.mcfunction", functions_path))
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    assert_eq!(blocks.len(), 2);
+
+    // Find the outer block by checking which file is called from main
+    let (outer, inner) = {
+        let first_func = format!("test:{}", blocks[0].file_stem().unwrap().to_str().unwrap());
+        if main_contents.contains(&first_func) {
+            (&blocks[0], &blocks[1])
+        } else {
+            (&blocks[1], &blocks[0])
+        }
+    };
+    let outer_func = format!("test:{}", outer.file_stem().unwrap().to_str().unwrap());
+    let inner_func = format!("test:{}", inner.file_stem().unwrap().to_str().unwrap());
+
+    assert!(main_contents.contains(&format!("execute as @a at @s run function {}", outer_func)));
+
+    let outer_contents = fs::read_to_string(outer).unwrap();
+    assert!(outer_contents.contains("say Inside block"));
+    assert!(outer_contents.contains(&format!(
+        "execute if entity @s[tag=nested] run function {}",
+        inner_func
+    )));
+
+    let inner_contents = fs::read_to_string(inner).unwrap();
+    assert!(inner_contents.contains("say Inside nested block"));
+}
+
+/// Test that function parameters and return values are stored in scoreboards
+#[test]
+fn test_function_params() {
+    let out = tests::run_in_tempdir("test_function_params").0;
+    let functions_path = format!("{}/data/test/functions", out.path().display());
+
+    let add_contents = fs::read_to_string(format!("{}/add.mcfunction", functions_path)).unwrap();
+    assert!(
+        add_contents.contains("scoreboard players operation --databind add.0 += --databind add.1")
+    );
+    assert!(add_contents.contains("scoreboard objectives add add.ret dummy"));
+    assert!(
+        add_contents.contains("scoreboard players operation --databind add.ret = --databind add.0")
+    );
+
+    let main_contents = fs::read_to_string(format!("{}/main.mcfunction", functions_path)).unwrap();
+    let expected_lines = [
+        "scoreboard objectives add add.0 dummy",
+        "scoreboard players set --databind add.0 2",
+        "scoreboard objectives add add.1 dummy",
+        "scoreboard players operation --databind add.1 = --databind x",
+        "function test:add",
+        "scoreboard players operation --databind add.0 = --databind x",
+        "scoreboard players set --databind add.1 3",
+        "execute as @a run function test:add",
+        "execute if score --databind add.ret matches 7 run say Sum is 7",
+    ];
+
+    for line in expected_lines.iter() {
+        assert!(main_contents.contains(line));
+        println!("Has line {}", line);
+    }
+
+    // Variables declared in other functions can be returned
+    let current_contents =
+        fs::read_to_string(format!("{}/current.mcfunction", functions_path)).unwrap();
+    assert!(current_contents
+        .contains("scoreboard players operation --databind current.ret = --databind x"));
+
+    // Minecraft's `return run` and `return fail` are left alone
+    let check_contents =
+        fs::read_to_string(format!("{}/check.mcfunction", functions_path)).unwrap();
+    assert!(check_contents.contains("execute unless entity @p run return fail\n"));
+    assert!(check_contents.contains("return run say Checked\n"));
+}
+
+/// Test that scheduled calls and `after` blocks are properly converted
+#[test]
+fn test_schedule() {
+    let out = tests::run_in_tempdir("test_schedule").0;
+    let functions_path = format!("{}/data/test/functions", out.path().display());
+
+    let main_contents = fs::read_to_string(format!("{}/main.mcfunction", functions_path)).unwrap();
+    assert!(main_contents.contains("schedule function test:later 20t"));
+
+    let after_files: Vec<PathBuf> = glob(&format!("{}/after_*.mcfunction", functions_path))
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    assert_eq!(after_files.len(), 1);
+
+    let after_func = format!(
+        "test:{}",
+        after_files[0].file_stem().unwrap().to_str().unwrap()
+    );
+    assert!(main_contents.contains(&format!("schedule function {} 5s", after_func)));
+
+    let after_contents = fs::read_to_string(&after_files[0]).unwrap();
+    assert!(after_contents.contains("say Inside after block"));
+}
+
+/// Test that function calls are resolved relative to the current folder
+#[test]
+fn test_relative_calls() {
+    let out = tests::run_in_tempdir("test_relative_calls").0;
+    let out_path = format!(
+        "{}/data/test/functions/cmd/main.mcfunction",
+        out.path().display()
+    );
+    let contents = fs::read_to_string(&out_path).unwrap();
+
+    let expected_lines = [
+        "function test:cmd/helper\nfunction test:cmd/helper",
+        "function test:util/helper\nfunction test:root_func",
+        "function test:root_func\nfunction test:util/helper",
+    ];
+
+    for line in expected_lines.iter() {
+        assert!(contents.contains(line));
+        println!("Has line {:?}", line);
+    }
+}
+
 /// Test that macros are properly replaced using different
 /// formatting
 #[test]
@@ -227,6 +360,128 @@ fn test_macro_replacement() {
     }
 }
 
+/// Test that macro arguments don't need to be quoted
+#[test]
+fn test_bare_macro_args() {
+    let out = tests::run_in_tempdir("test_bare_macro_args").0;
+
+    let out_path = format!(
+        "{}/data/test/functions/main.mcfunction",
+        out.path().display()
+    );
+    let contents = fs::read_to_string(&out_path).unwrap();
+
+    let expected_lines = [
+        "give @s diamond 5",
+        "give @s golden_apple 2",
+        "kill @e[type=zombie,limit=1]",
+        "tellraw @a {\"text\":\"Hello, World!\",\"color\":\"red\"}",
+        "tellraw @a [\"a\", {\"text\":\"(b)\"}]",
+    ];
+
+    for line in expected_lines.iter() {
+        assert!(contents.contains(line));
+        println!("Has line {}", line);
+    }
+}
+
+/// Test default values, named arguments, and variadic parameters for macros
+#[test]
+fn test_macro_params() {
+    let out = tests::run_in_tempdir("test_macro_params").0;
+
+    let out_path = format!(
+        "{}/data/test/functions/main.mcfunction",
+        out.path().display()
+    );
+    let contents = fs::read_to_string(&out_path).unwrap();
+
+    let expected_lines = [
+        "say a=Test 1, b=default b, rest=\n",
+        "say a=Test 2, b=b, rest=\n",
+        "say a=Test 3, b=b, rest=c, d\n",
+        "say a=Test 4, b=default b, rest=\n",
+        "say a=Test 5, b=named b, rest=\n",
+        "say a=Test 6, b=named b, rest=\n",
+    ];
+
+    for line in expected_lines.iter() {
+        assert!(contents.contains(line));
+        println!("Has line {:?}", line);
+    }
+}
+
+/// Test that macro parameters are only replaced as whole identifiers
+#[test]
+fn test_macro_hygiene() {
+    let out = tests::run_in_tempdir("test_macro_hygiene").0;
+
+    let out_path = format!(
+        "{}/data/test/functions/main.mcfunction",
+        out.path().display()
+    );
+    let contents = fs::read_to_string(&out_path).unwrap();
+
+    let expected_lines = [
+        "say first $abc second\n",
+        "say first_suffix\n",
+        "say Costs $5\n",
+        "say contains $b $abc second\n",
+    ];
+
+    for line in expected_lines.iter() {
+        assert!(contents.contains(line));
+        println!("Has line {:?}", line);
+    }
+}
+
+/// Test `!if` and `!for` blocks in macros
+#[test]
+fn test_macro_control_flow() {
+    let out = tests::run_in_tempdir("test_macro_control_flow").0;
+
+    let out_path = format!(
+        "{}/data/test/functions/main.mcfunction",
+        out.path().display()
+    );
+    let contents = fs::read_to_string(&out_path).unwrap();
+
+    let expected = "say [debug] First message\n\
+        say Second message\n\
+        say wool_red_0\n\
+        say wool_red_1\n\
+        say wool_blue_0\n\
+        say wool_blue_1\n\
+        say z is not x==y\n\
+        say x!=y is not x==y\n\
+        say x!=y is x!=y\n";
+
+    assert_eq!(contents, expected);
+}
+
+/// Test importing macros from other files, `!use`, and using macros
+/// before they're defined
+#[test]
+fn test_macro_imports() {
+    let out = tests::run_in_tempdir("test_macro_imports").0;
+
+    let out_path = format!(
+        "{}/data/test/functions/main.mcfunction",
+        out.path().display()
+    );
+    let contents = fs::read_to_string(&out_path).unwrap();
+
+    let expected = "say math 1 + 2\n\
+        say text ab\n\
+        say math 3 + 3\n\
+        say note: double\n\
+        say text cd\n\
+        say later 4\n\
+        say defined in another file\n";
+
+    assert_eq!(contents, expected);
+}
+
 /// Test that macros calling other macros work properly
 #[test]
 fn test_macro_recursion() {
@@ -253,6 +508,9 @@ fn test_global_vars() {
 
     assert!(contents.contains("say Variable 1"));
     assert!(contents.contains("tellraw @a \"Variable 2\""));
+    // Undefined names in strings are left alone
+    assert!(contents.contains("tellraw @a \"Q&A with Variable 1\""));
+    assert!(contents.contains("set value '{\"text\":\"R&D\"}'"));
 }
 
 /// Test that multiple TOML types are properly supported by
@@ -282,6 +540,60 @@ fn test_types_global_vars() {
     }
 }
 
+/// Test that global vars are only replaced as whole names
+/// and can be escaped
+#[test]
+fn test_var_substitution() {
+    let out = tests::run_in_tempdir("test_var_substitution").0;
+
+    let out_path = format!(
+        "{}/data/test/functions/main.mcfunction",
+        out.path().display()
+    );
+
+    let expected_lines = [
+        "say name=World names=Everyone",
+        "say escaped=&name",
+        "say end=World.",
+        "say amp=&name",
+        "tellraw @a \"A & B\"",
+    ];
+
+    let contents = fs::read_to_string(&out_path).unwrap();
+
+    for line in expected_lines.iter() {
+        assert!(contents.contains(line));
+        println!("Line {:?} found", line);
+    }
+}
+
+/// Test that arrays and tables can be used as global vars
+#[test]
+fn test_structured_vars() {
+    let out = tests::run_in_tempdir("test_structured_vars").0;
+
+    let out_path = format!(
+        "{}/data/test/functions/main.mcfunction",
+        out.path().display()
+    );
+
+    let expected_lines = [
+        "say first=red",
+        "say second=blue",
+        "say size=2",
+        "say team=red size=4",
+        "say spawn=10 64",
+        "give @a red_wool\ngive @a blue_wool",
+    ];
+
+    let contents = fs::read_to_string(&out_path).unwrap();
+
+    for line in expected_lines.iter() {
+        assert!(contents.contains(line));
+        println!("Line {:?} found", line);
+    }
+}
+
 /// Test that global macros are properly dealt with
 #[test]
 fn test_global_macros() {
@@ -338,3 +650,322 @@ fn test_modulus_operator() {
         contents.contains("scoreboard players operation testplayer score1 %= testplayer score2")
     );
 }
+
+/// Test that macros from the standard library are loaded when enabled in the config
+#[test]
+fn test_std_macros() {
+    let mut path = tests::resources();
+    path.push("test_std_macros");
+    let path_str = path.to_str().unwrap();
+
+    let out = TempDir::new("test_std_macros").expect("Could not create tempdir for test");
+
+    let toml_path = format!("{}/databind.toml", path_str);
+
+    let args = if cfg!(debug_assertions) {
+        vec![
+            "run",
+            "--",
+            path_str,
+            "--config",
+            &toml_path,
+            "--out",
+            out.path().to_str().unwrap(),
+        ]
+    } else {
+        vec![
+            "run",
+            "--release",
+            "--",
+            path_str,
+            "--config",
+            &toml_path,
+            "--out",
+            out.path().to_str().unwrap(),
+        ]
+    };
+
+    tests::run_with_args("cargo", &args, None);
+
+    let functions = format!("{}/data/test/functions", out.path().display());
+    let main = fs::read_to_string(format!("{}/main.mcfunction", functions)).unwrap();
+
+    let expected_lines = [
+        "scoreboard players operation --databind roll %= range databind_random\n",
+        "tellraw @a {\"text\":\"Rolled\",\"color\":\"gold\"}\n",
+        "kill @e[type=zombie,sort=nearest,limit=1]\n",
+        "execute if score --databind roll matches 5.. run scoreboard players set --databind roll 5\n",
+    ];
+
+    for line in expected_lines.iter() {
+        assert!(main.contains(line));
+        println!("Has line {:?}", line);
+    }
+
+    let tick = fs::read_to_string(format!("{}/heartbeat_tick.mcfunction", functions)).unwrap();
+    assert!(tick.contains("run function test:heartbeat_done\n"));
+
+    let step = fs::read_to_string(format!("{}/look_step.mcfunction", functions)).unwrap();
+    assert!(step.contains("positioned ^ ^ ^0.5 run function test:look_step\n"));
+}
+
+/// Test that profiles and `-D` override settings and vars
+#[test]
+fn test_profiles() {
+    let mut path = tests::resources();
+    path.push("test_profiles");
+
+    let read_main = |out: &TempDir| {
+        fs::read_to_string(format!(
+            "{}/data/test/functions/main.mcfunction",
+            out.path().display()
+        ))
+        .unwrap()
+    };
+
+    let out = TempDir::new("test_profiles").expect("Could not create tempdir for test");
+    tests::run_with_config(out.path(), &path, &[]);
+    assert!(read_main(&out).contains("say debug=0 name=World level=1"));
+
+    let out = TempDir::new("test_profiles_debug").expect("Could not create tempdir for test");
+    tests::run_with_config(out.path(), &path, &["--profile", "debug"]);
+    assert!(read_main(&out).contains("say debug=1 name=World level=1"));
+
+    let out = TempDir::new("test_profiles_release").expect("Could not create tempdir for test");
+    tests::run_with_config(
+        out.path(),
+        &path,
+        &[
+            "--profile",
+            "release",
+            "-D",
+            "level=3",
+            "-D",
+            "name=Everyone",
+        ],
+    );
+    assert!(read_main(&out).contains("say debug=0 name=Everyone level=3"));
+    assert!(fs::metadata(format!(
+        "{}/data/test/functions/debug_info.mcfunction",
+        out.path().display()
+    ))
+    .is_err());
+
+    // -D arguments with the names of settings change the settings instead
+    let out = TempDir::new("test_profiles_settings").expect("Could not create tempdir for test");
+    tests::run_with_config(out.path(), &path, &["-D", "header=# Test build"]);
+    assert!(read_main(&out).starts_with("# Test build\nsay debug=0 name=World level=1"));
+
+    // An explicit -o overrides -D output=...
+    let out = TempDir::new("test_profiles_output").expect("Could not create tempdir for test");
+    let ignored = TempDir::new("test_profiles_ignored").expect("Could not create tempdir for test");
+    let define = format!("output={}", ignored.path().display());
+    tests::run_with_config(out.path(), &path, &["-D", &define]);
+    assert!(read_main(&out).contains("say debug=0 name=World level=1"));
+    assert!(fs::read_dir(ignored.path()).unwrap().next().is_none());
+
+    let stderr = tests::run_failing_with_config("test_profiles", &["-D", "max_macro_depth=deep"]);
+    assert!(stderr.contains("error: Invalid setting from -D: invalid type"));
+}
+
+/// Test that built-in global vars are defined and can be used in the header
+#[test]
+fn test_builtin_vars() {
+    let mut path = tests::resources();
+    path.push("test_builtin_vars");
+
+    let out = TempDir::new("test_builtin_vars").expect("Could not create tempdir for test");
+    tests::run_with_config(out.path(), &path, &[]);
+
+    let contents = fs::read_to_string(format!(
+        "{}/data/builtin/functions/main.mcfunction",
+        out.path().display()
+    ))
+    .unwrap();
+    let version = env!("CARGO_PKG_VERSION");
+
+    assert!(contents.starts_with(&format!("# Built by databind {} for builtin\n", version)));
+    assert!(contents.contains(&format!("say version={}\n", version)));
+    assert!(contents.contains("say namespace=builtin\n"));
+    assert!(contents.contains("say pack=test_builtin_vars format=7\n"));
+    assert!(
+        Regex::new(r"say time=\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z\n")
+            .unwrap()
+            .is_match(&contents)
+    );
+    assert!(Regex::new(r"say commit=\S+\n").unwrap().is_match(&contents));
+}
+
+/// Test that constants are replaced and folded in their scope
+#[test]
+fn test_consts() {
+    let out = tests::run_in_tempdir("test_consts").0;
+    let functions = format!("{}/data/test/functions", out.path().display());
+
+    let main = fs::read_to_string(format!("{}/main.mcfunction", functions)).unwrap();
+    let expected_lines = [
+        "scoreboard players set --databind hp 20\n",
+        "say bonus=22 local=5\n",
+        "say Hello, world\n",
+        "say escaped=MAX_HP\n",
+        "tellraw @a \"MAX_HP\"\n",
+        "execute if score @s hp matches ..10 run",
+        "say heal=22\n",
+        // Names that are part of objectives, NBT, or function names aren't replaced
+        "scoreboard players set @s stats.LOCAL 1\n",
+        "scoreboard players set @s LOCAL.total 1\n",
+        "scoreboard players set @s max-LOCAL 1\n",
+        "data merge entity @s {LOCAL:1b}\n",
+        "kill @e[limit=5]\n",
+        "function test:LOCAL\n",
+        // Words in text only match constants with the same UPPER_CASE name
+        "say level up to level 3\n",
+        "tellraw @a {\"text\":\"LEVEL up\",\"color\":\"gold\"}\n",
+        "give @s diamond 3\n",
+    ];
+    for line in expected_lines.iter() {
+        assert!(main.contains(line));
+        println!("Line {:?} found", line);
+    }
+
+    // Constants declared without `pub` can only be used in their file
+    let values = fs::read_to_string(format!("{}/values.mcfunction", functions)).unwrap();
+    assert!(values.contains("say half=10 local=LOCAL\n"));
+}
+
+/// Test that conditional compilation blocks use vars, profiles, and features
+#[test]
+fn test_cfg() {
+    let mut path = tests::resources();
+    path.push("test_cfg");
+
+    let read_main = |out: &TempDir| {
+        fs::read_to_string(format!(
+            "{}/data/test/functions/main.mcfunction",
+            out.path().display()
+        ))
+        .unwrap()
+    };
+
+    let out = TempDir::new("test_cfg").expect("Could not create tempdir for test");
+    tests::run_with_config(out.path(), &path, &[]);
+    assert_eq!(
+        read_main(&out),
+        "say debug off\nsay not debug\nsay java without extra\n"
+    );
+
+    let out = TempDir::new("test_cfg_debug").expect("Could not create tempdir for test");
+    tests::run_with_config(
+        out.path(),
+        &path,
+        &["--profile", "debug", "--features", "extra"],
+    );
+    assert_eq!(
+        read_main(&out),
+        "say debug on yes\nsay [log] Logging enabled\nsay extra or bedrock\n"
+    );
+}
+
+/// Test databind.toml files in subdirectories
+#[test]
+fn test_dir_configs() {
+    let mut path = tests::resources();
+    path.push("test_dir_configs");
+
+    let out = TempDir::new("test_dir_configs").expect("Could not create tempdir for test");
+    tests::run_with_config(out.path(), &path, &[]);
+
+    let read = |file: &str| fs::read_to_string(format!("{}/data/{}", out.path().display(), file));
+
+    assert_eq!(
+        read("test/functions/main.mcfunction").unwrap(),
+        "# test_dir_configs\nsay root\nfunction mylib:util/helper\n"
+    );
+    assert_eq!(
+        read("mylib/functions/util/helper.mcfunction").unwrap(),
+        "# mylib\nsay fast lib\nfunction mylib:util/inner/deep\n"
+    );
+    assert_eq!(
+        read("mylib/functions/util/inner/deep.mcfunction").unwrap(),
+        "# mylib\nsay deep mylib\n"
+    );
+
+    // Excluded files and the configs themselves aren't in the output
+    assert!(read("mylib/functions/util/old.mcfunction").is_err());
+    let configs = glob(&format!("{}/**/databind.toml", out.path().display())).unwrap();
+    assert_eq!(configs.count(), 0);
+}
+
+/// Test generating pack.mcmeta from the `[pack]` table
+#[test]
+fn test_pack_mcmeta() {
+    let mut path = tests::resources();
+    path.push("test_pack_mcmeta");
+
+    let out = TempDir::new("test_pack_mcmeta").expect("Could not create tempdir for test");
+    let output = tests::run_with_config(out.path(), &path, &[]);
+    let stderr = str::from_utf8(&output.stderr).unwrap();
+    assert!(stderr.contains("pack.mcmeta is ignored because databind.toml has a [pack] table"));
+
+    let contents = fs::read_to_string(out.path().join("pack.mcmeta")).unwrap();
+    let pack_mcmeta: serde_json::Value = serde_json::from_str(&contents).unwrap();
+    let expected = serde_json::json!({
+        "pack": {
+            "pack_format": 15,
+            "description": { "text": "A test pack", "color": "gold" },
+            "supported_formats": [15, 18]
+        },
+        "overlays": {
+            "entries": [
+                {
+                    "formats": { "min_inclusive": 18, "max_inclusive": 18 },
+                    "directory": "overlay_18"
+                }
+            ]
+        },
+        "filter": {
+            "block": [{ "namespace": "minecraft", "path": "recipes/.*" }]
+        }
+    });
+    assert_eq!(pack_mcmeta, expected);
+
+    // The [pack] table is used for the built-in variables
+    let main = fs::read_to_string(out.path().join("data/test/functions/main.mcfunction")).unwrap();
+    assert_eq!(main, "say Test Pack 15\n");
+}
+
+/// Test that `minecraft_version` sets the pack format, folder names,
+/// and commands used, and warns about unavailable commands
+#[test]
+fn test_minecraft_version() {
+    let mut path = tests::resources();
+    path.push("test_minecraft_version");
+
+    let out = TempDir::new("test_minecraft_version").expect("Could not create tempdir for test");
+    let output = tests::run_with_config(out.path(), &path, &[]);
+    let stderr = str::from_utf8(&output.stderr).unwrap();
+    assert!(stderr.contains(
+        "The `replaceitem` command isn't available in Minecraft 1.21, but is used in test:main\nnote: it was removed in 1.17"
+    ));
+    assert!(stderr.contains(
+        "The `rotate` command isn't available in Minecraft 1.21, but is used in test:main\nnote: it was added in 1.21.2"
+    ));
+
+    let contents = fs::read_to_string(out.path().join("pack.mcmeta")).unwrap();
+    let pack_mcmeta: serde_json::Value = serde_json::from_str(&contents).unwrap();
+    assert_eq!(pack_mcmeta["pack"]["pack_format"], 48);
+
+    // 1.21 uses `function` folders instead of `functions`
+    assert!(!out.path().join("data/test/functions").exists());
+    let five = fs::read_to_string(out.path().join("data/test/function/five.mcfunction")).unwrap();
+    assert!(five.contains("scoreboard players set --databind five.ret 5\nreturn 5\n"));
+    // `return` doesn't stop the function, so only one at the end uses the command
+    let early = fs::read_to_string(out.path().join("data/test/function/early.mcfunction")).unwrap();
+    assert!(!early.contains("return 1"));
+    assert!(early.ends_with("say still runs\n"));
+    let main = fs::read_to_string(out.path().join("data/test/function/main.mcfunction")).unwrap();
+    assert!(main.starts_with("say 1.21 48\n"));
+    let load =
+        fs::read_to_string(out.path().join("data/minecraft/tags/function/load.json")).unwrap();
+    assert_eq!(load, r#"{"values":["test:five"]}"#);
+}
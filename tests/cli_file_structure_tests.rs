@@ -195,7 +195,7 @@ fn test_tag_generation() {
     tests::check_files_exist(&path, &expected_tags, "test_tag_generation");
     // Check tag file contents
     for i in 0..expected_tags.len() {
-        path.push(&expected_tags[i]);
+        path.push(expected_tags[i]);
         let contents = fs::read_to_string(&path).unwrap();
         let contents_tag: TagFile = serde_json::from_str(&contents).unwrap();
         let expected_tag = TagFile {
@@ -282,20 +282,21 @@ fn test_while_structure() {
     .filter_map(Result::ok)
     .collect();
 
+    let while_re = Regex::new("while_[0-9a-z]{4}.mcfunction").unwrap();
+    let condition_re = Regex::new("condition_[0-9a-z]{4}.mcfunction").unwrap();
+
     for file in files.iter() {
         let file_str = file
             .to_str()
             .unwrap()
-            .split(|x: char| ['\\', '/'].contains(&x))
-            .last()
+            .rsplit(|x: char| ['\\', '/'].contains(&x))
+            .next()
             .unwrap();
 
         if file_str.starts_with("while") {
-            let re = Regex::new("while_[0-9a-z]{4}.mcfunction").unwrap();
-            assert!(re.is_match(file_str));
+            assert!(while_re.is_match(file_str));
         } else if file_str.starts_with("condition") {
-            let re = Regex::new("condition_[0-9a-z]{4}.mcfunction").unwrap();
-            assert!(re.is_match(file_str));
+            assert!(condition_re.is_match(file_str));
         } else {
             assert!(file_str == "main.mcfunction");
         }
@@ -319,23 +320,24 @@ fn test_if_structure() {
     .filter_map(Result::ok)
     .collect();
 
+    let if_init_re = Regex::new("if_init_[0-9a-z]{4}.mcfunction").unwrap();
+    let if_true_re = Regex::new("if_true_[0-9a-z]{4}.mcfunction").unwrap();
+    let if_false_re = Regex::new("if_false_[0-9a-z]{4}.mcfunction").unwrap();
+
     for file in files.iter() {
         let file_str = file
             .to_str()
             .unwrap()
-            .split(|x: char| ['\\', '/'].contains(&x))
-            .last()
+            .rsplit(|x: char| ['\\', '/'].contains(&x))
+            .next()
             .unwrap();
 
         if file_str.starts_with("if_init") {
-            let re = Regex::new("if_init_[0-9a-z]{4}.mcfunction").unwrap();
-            assert!(re.is_match(file_str));
+            assert!(if_init_re.is_match(file_str));
         } else if file_str.starts_with("if_true_") {
-            let re = Regex::new("if_true_[0-9a-z]{4}.mcfunction").unwrap();
-            assert!(re.is_match(file_str));
+            assert!(if_true_re.is_match(file_str));
         } else if file_str.starts_with("if_false_") {
-            let re = Regex::new("if_false_[0-9a-z]{4}.mcfunction").unwrap();
-            assert!(re.is_match(file_str));
+            assert!(if_false_re.is_match(file_str));
         } else {
             assert!(file_str == "main.mcfunction");
         }
@@ -15,16 +15,17 @@
  * You should have received a copy of the GNU General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use std::fs;
 use std::path::PathBuf;
+use std::str;
 use tempdir::TempDir;
 
 mod tests;
 
 /// Test the `databind create` file structure.
-/// Also tests the contents of the config file and
-/// the `pack.mcmeta` file
+/// Also tests the contents of the config file,
+/// including the pack metadata used for `pack.mcmeta`
 #[test]
 fn test_create_structure() {
     let out = TempDir::new("test_create_structure").expect("Could not create tempdir for test");
@@ -64,6 +65,14 @@ fn test_create_structure() {
         path.push("databind.toml");
         assert!(path.exists() && path.is_file());
 
+        /// Inside the `[pack]` table.
+        /// Taken from `pack.rs`
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Pack {
+            pack_format: u8,
+            description: String,
+        }
+
         /// Settings for the compiler.
         /// Taken from `settings.rs`
         #[derive(Debug, PartialEq, Deserialize)]
@@ -71,54 +80,240 @@ fn test_create_structure() {
             pub inclusions: Vec<String>,
             pub exclusions: Vec<String>,
             pub output: String,
+            pack: Pack,
         }
 
         // Check config file contents
         let contents = fs::read_to_string(&path).unwrap();
         let contents_config: Settings = toml::from_str(&contents).unwrap();
-        // Same as Settings::default()
+        // Same as Settings::default() with the pack metadata
         let expected_config = Settings {
             inclusions: vec!["**/*.databind".into()],
             exclusions: Vec::new(),
             output: "out".into(),
-        };
-        assert_eq!(contents_config, expected_config);
-        path.pop();
-    }
-
-    {
-        // Check that the pack.mcmeta file was created
-        path.push("src/pack.mcmeta");
-        assert!(path.exists() && path.is_file());
-
-        /// Inside pack.mcmeta.
-        /// Taken from `create_project.rs`
-        #[derive(Debug, PartialEq, Serialize, Deserialize)]
-        struct Pack {
-            pack_format: u8,
-            description: String,
-        }
-
-        /// pack.mcmeta.
-        /// Taken from `create_project.rs`
-        #[derive(Debug, PartialEq, Serialize, Deserialize)]
-        struct PackMcMeta {
-            pack: Pack,
-        }
-
-        let contents = fs::read_to_string(&path).unwrap();
-        let contents_pack: PackMcMeta = serde_json::from_str(&contents).unwrap();
-        let expected_pack = PackMcMeta {
             pack: Pack {
                 pack_format: 7,
                 description: "test_create_structure description".into(),
             },
         };
-        assert_eq!(contents_pack, expected_pack);
+        assert_eq!(contents_config, expected_config);
         path.pop();
     }
 
+    // pack.mcmeta is generated when the project is compiled
+    path.push("src/pack.mcmeta");
+    assert!(!path.exists());
+    path.pop();
+
     // Check that the main.databind file was created
     path.push("data/test_create_structure/functions/main.databind");
     assert!(path.exists() && path.is_file());
 }
+
+/// Test that referencing a function or tag that doesn't exist fails to compile
+#[test]
+fn test_missing_function() {
+    let stderr = tests::run_failing("test_missing_function");
+
+    assert!(stderr.contains("error: Function test:does_not_exist referenced in"));
+    assert!(stderr.contains("error: Function test:helpr referenced in"));
+    assert!(stderr.contains("help: did you mean test:helper?"));
+    assert!(stderr.contains("error: Function tag #minecraft:lod referenced in"));
+    assert!(stderr.contains("help: did you mean #minecraft:load?"));
+}
+
+/// Test that calling a macro with the wrong number of arguments fails to compile
+#[test]
+fn test_macro_arity() {
+    let stderr = tests::run_failing("test_macro_arity");
+
+    assert!(stderr
+        .contains("error: Too many arguments (expected at most 2, got 3) in call of ?two_args at"));
+}
+
+/// Test that a macro with more than one variadic parameter, or with a variadic
+/// parameter before another parameter, fails to compile where it's defined
+#[test]
+fn test_variadic_param_errors() {
+    let stderr = tests::run_failing("test_variadic_multiple");
+
+    assert!(stderr.contains("error: Macro ?m has more than one variadic parameter at"));
+    assert!(stderr.contains("main.databind:1:15\nhelp: remove the `...` from $a or $b"));
+
+    let stderr = tests::run_failing("test_variadic_not_last");
+
+    assert!(stderr.contains("error: Variadic parameter $rest of ?n must be the last parameter at"));
+    assert!(stderr.contains("main.databind:1:8\nhelp: move $rest... after $x"));
+}
+
+/// Test that macros nested deeper than `max_macro_depth` fail to compile
+#[test]
+fn test_macro_depth() {
+    let stderr = tests::run_failing_with_config("test_macro_depth", &[]);
+
+    assert!(stderr.contains("error: Macro expansion depth limit of 3 exceeded in call of ?d at"));
+    assert!(stderr.contains("note: call chain: ?a -> ?b -> ?c -> ?d"));
+}
+
+/// Test that errors inside of macro expansions show the call site
+/// and the location in each macro's definition
+#[test]
+fn test_macro_errors() {
+    let stderr = tests::run_failing("test_macro_errors");
+
+    assert!(stderr.contains("main.databind:13:5\n"));
+    assert!(stderr.contains("note: in expansion of ?inner at "));
+    assert!(stderr.contains("main.databind:3:9\n"));
+    assert!(stderr.contains("note: in expansion of ?outer at "));
+    assert!(stderr.contains("main.databind:8:5\n"));
+}
+
+/// Test that calling a macro that doesn't exist fails with suggestions instead of panicking
+#[test]
+fn test_undefined_macro() {
+    let stderr = tests::run_failing("test_undefined_macro");
+
+    assert!(stderr.contains("error: No macro named ?gret is defined at"));
+    assert!(stderr.contains("main.databind:6:5\nhelp: did you mean ?greet?"));
+}
+
+/// Test that a vars.toml that isn't valid TOML fails with its location
+#[test]
+fn test_invalid_vars() {
+    let stderr = tests::run_failing("test_invalid_vars");
+
+    assert!(stderr.contains("error: Invalid vars file "));
+    assert!(stderr.contains("at line 3 column 1"));
+}
+
+/// Test that using a global variable that isn't defined fails with a suggestion,
+/// including after an apostrophe
+#[test]
+fn test_undefined_var() {
+    let stderr = tests::run_failing("test_undefined_var");
+
+    assert!(stderr.contains("error: No global variable named &nme is defined at"));
+    assert!(stderr.contains("main.databind:3:21\nhelp: did you mean &name?"));
+}
+
+/// Test that selecting a profile that doesn't exist fails with a suggestion
+#[test]
+fn test_unknown_profile() {
+    let stderr = tests::run_failing_with_config("test_profiles", &["--profile", "relase"]);
+
+    assert!(stderr.contains(
+        "error: No profile named relase is defined in databind.toml\nhelp: did you mean release?"
+    ));
+}
+
+/// Test that constants declared twice, depending on themselves, or with
+/// lowercase names fail to compile
+#[test]
+fn test_const_errors() {
+    let stderr = tests::run_failing("test_const_errors");
+
+    assert!(stderr.contains("error: Constant SIZE is already defined at"));
+    assert!(stderr.contains("main.databind:7:7\nnote: first defined at "));
+    assert!(stderr.contains("main.databind:1:7\n"));
+
+    let stderr = tests::run_failing("test_const_cycle");
+
+    assert!(stderr.contains("error: Constant A depends on itself (A -> B -> A) at"));
+    assert!(stderr.contains("main.databind:5:9\nnote: in constant B defined at "));
+
+    let stderr = tests::run_failing("test_const_lowercase");
+
+    assert!(stderr.contains("error: Constant names must be UPPER_CASE at"));
+    assert!(stderr.contains("main.databind:1:7\nhelp: rename level to LEVEL"));
+}
+
+/// Test that `return` outside of a function fails to compile
+#[test]
+fn test_return_errors() {
+    let stderr = tests::run_failing("test_return_errors");
+
+    assert!(stderr.contains("error: Found `return` outside of a function at"));
+    assert!(stderr.contains("main.databind:5:1\nhelp: move it into a `func` block"));
+}
+
+/// Test that returning a variable that isn't declared anywhere is an error
+#[test]
+fn test_return_undefined_var() {
+    let stderr = tests::run_failing("test_return_undefined_var");
+
+    assert!(stderr.contains("error: No variable named `totl` is defined at"));
+    assert!(stderr.contains("main.databind:3:5\nhelp: did you mean `total`?"));
+}
+
+/// Test that an `!ifdef` block without an `!endif` fails to compile
+#[test]
+fn test_cfg_errors() {
+    let stderr = tests::run_failing("test_cfg_errors");
+
+    assert!(stderr.contains("error: Unclosed `!ifdef` block at"));
+    assert!(stderr.contains("main.databind:2:5\nhelp: add an `!endif` to end it"));
+}
+
+/// Test that unknown settings are warnings with suggestions,
+/// and settings with the wrong type are errors
+#[test]
+fn test_config_validation() {
+    let mut path = tests::resources();
+    path.push("test_config_warnings");
+
+    let out = TempDir::new("test_config_warnings").expect("Could not create tempdir for test");
+    let output = tests::run_with_config(out.path(), &path, &[]);
+    let stderr = str::from_utf8(&output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert!(stderr.contains("warning: Unknown setting `inclusion` at"));
+    assert!(stderr.contains("databind.toml:2\nhelp: did you mean `inclusions`?"));
+    assert!(stderr.contains("warning: Unknown setting `headr` in profile debug at"));
+    assert!(stderr.contains("databind.toml:6\nhelp: did you mean `header`?"));
+
+    let stderr = tests::run_failing_with_config("test_invalid_config", &[]);
+
+    assert!(stderr.contains(
+        "error: Invalid config: invalid type: string \"deep\", expected usize for key `max_macro_depth` at"
+    ));
+    assert!(stderr.contains("databind.toml:3:19"));
+}
+
+/// Test that a subdirectory config changing a project-wide setting fails
+#[test]
+fn test_dir_config_errors() {
+    let stderr = tests::run_failing("test_dir_config_errors");
+
+    assert!(stderr.contains(
+        "error: The std_macros setting can't be changed in src/data/test/functions/lib/databind.toml"
+    ));
+}
+
+/// Test that `databind expand` prints a file with its macros expanded
+#[test]
+fn test_expand() {
+    let mut path = tests::resources();
+    path.push("test_expand/src/data/test/functions/main.databind");
+
+    let args = if cfg!(debug_assertions) {
+        vec!["run", "--", "expand", path.to_str().unwrap()]
+    } else {
+        vec!["run", "--release", "--", "expand", path.to_str().unwrap()]
+    };
+    let output = tests::run_with_args("cargo", &args, None);
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        stdout,
+        "func main
+    tellraw @a \"Hello, World!\"
+    while tag @s[tag=looping]
+        say Looping
+        say Looping
+    end
+    say Trusted
+end
+"
+    );
+}
@@ -0,0 +1 @@
+say root_func
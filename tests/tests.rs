@@ -47,8 +47,9 @@ pub fn resources() -> PathBuf {
 ///
 /// - `files` - A list of paths to files
 /// - `print_prefix` - A prefix for the message after assertion
-pub fn check_files_exist<P: AsRef<Path>>(base_path: &PathBuf, files: &[P], print_prefix: &str) {
-    let mut base = base_path.clone();
+#[allow(dead_code)]
+pub fn check_files_exist<P: AsRef<Path>>(base_path: &Path, files: &[P], print_prefix: &str) {
+    let mut base = base_path.to_path_buf();
     // let base_dirs = base.ancestors().collect::<Vec<&Path>>().len();
 
     for file in files.iter() {
@@ -69,17 +70,14 @@ pub fn check_files_exist<P: AsRef<Path>>(base_path: &PathBuf, files: &[P], print
 ///
 /// - `files` - A list of paths to files
 /// - `print_prefix` - A prefix for the message after assertion
-pub fn check_files_dont_exist<P: AsRef<Path>>(
-    base_path: &PathBuf,
-    files: &[P],
-    print_prefix: &str,
-) {
-    let mut base = base_path.clone();
+#[allow(dead_code)]
+pub fn check_files_dont_exist<P: AsRef<Path>>(base_path: &Path, files: &[P], print_prefix: &str) {
+    let mut base = base_path.to_path_buf();
     // let base_dirs = base.ancestors().collect::<Vec<&Path>>().len();
 
     for file in files.iter() {
         base.push(file);
-        assert!(fs::metadata(&file).is_err());
+        assert!(fs::metadata(file).is_err());
         println!(
             "{} File {} does not exist (and shouldn't)",
             print_prefix,
@@ -90,7 +88,7 @@ pub fn check_files_dont_exist<P: AsRef<Path>>(
 }
 
 /// Run Databind on a path and send output to a path
-pub fn run<P: AsRef<Path>>(out: P, path: P) {
+pub fn run<P: AsRef<Path>>(out: P, path: P) -> Output {
     let args = if cfg!(debug_assertions) {
         vec![
             "run",
@@ -112,11 +110,67 @@ pub fn run<P: AsRef<Path>>(out: P, path: P) {
         ]
     };
 
-    run_with_args("cargo", &args, None);
+    run_with_args("cargo", &args, None)
+}
+
+/// Run Databind on a path using its config file and extra arguments
+#[allow(dead_code)]
+pub fn run_with_config<P: AsRef<Path>>(out: P, path: P, extra_args: &[&str]) -> Output {
+    let mut args = if cfg!(debug_assertions) {
+        vec!["run", "--"]
+    } else {
+        vec!["run", "--release", "--"]
+    };
+    args.extend_from_slice(&[
+        path.as_ref().to_str().unwrap(),
+        "--out",
+        out.as_ref().to_str().unwrap(),
+    ]);
+    args.extend_from_slice(extra_args);
+
+    run_with_args("cargo", &args, None)
+}
+
+/// Run Databind on a resource that should fail to compile, ignoring its config file.
+/// Returns what was printed to stderr
+#[allow(dead_code)]
+pub fn run_failing(resource: &str) -> String {
+    check_failed(resource, |out, path| run(out, path))
+}
+
+/// Run Databind on a resource that should fail to compile, using its config file
+/// and extra arguments. Returns what was printed to stderr
+#[allow(dead_code)]
+pub fn run_failing_with_config(resource: &str, extra_args: &[&str]) -> String {
+    check_failed(resource, |out, path| run_with_config(out, path, extra_args))
+}
+
+/// Run Databind on a resource with a temporary output directory
+/// and check that it failed without panicking
+#[allow(dead_code)]
+fn check_failed<F>(resource: &str, run: F) -> String
+where
+    F: FnOnce(&Path, &Path) -> Output,
+{
+    let mut path = resources();
+    path.push(resource);
+
+    let out = TempDir::new(resource).expect("Could not create tempdir for test");
+    let output = run(out.path(), &path);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(
+        !output.status.success(),
+        "{} compiled without errors",
+        resource
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    stderr
 }
 
 /// Create a temporary output directory for a test and run
 /// Databind there
+#[allow(dead_code)]
 pub fn run_in_tempdir(directory: &str) -> (TempDir, PathBuf) {
     let mut path = resources();
     path.push(directory);